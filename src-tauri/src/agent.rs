@@ -0,0 +1,242 @@
+use serde::{Deserialize, Serialize};
+use ssh_key::{PrivateKey, Signature, SigningKey};
+use std::io::{self, ErrorKind, Read, Write};
+use log::{info, warn};
+
+use crate::crypto::SecureStorage;
+use crate::inbound::Peer;
+use crate::keys::{self, PassphraseCache};
+
+/// Longest agent message accepted, as in OpenSSH.
+const MAX_MESSAGE_LEN: usize = 256 * 1024;
+/// Agent protocol message numbers (draft-miller-ssh-agent).
+const SSH_AGENT_FAILURE: u8 = 5;
+const SSH_AGENTC_REQUEST_IDENTITIES: u8 = 11;
+const SSH_AGENT_IDENTITIES_ANSWER: u8 = 12;
+const SSH_AGENTC_SIGN_REQUEST: u8 = 13;
+const SSH_AGENT_SIGN_RESPONSE: u8 = 14;
+/// Sign request flag asking for an `rsa-sha2-512` signature.
+const SSH_AGENT_RSA_SHA2_512: u32 = 4;
+
+/// Where a forwarded agent gets its keys from.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum AgentSource {
+    /// The agent running on this machine: `SSH_AUTH_SOCK`, or the OpenSSH
+    /// agent's pipe on Windows.
+    Local,
+    /// Keys in the app's key store. Encrypted ones are only offered while
+    /// their passphrase is cached.
+    Stored,
+}
+
+/// A stored key with its public key blob, as the server refers to it.
+struct StoredKey {
+    name: String,
+    blob: Vec<u8>,
+    key: PrivateKey,
+}
+
+enum Backend {
+    Local(Box<dyn AgentStream>),
+    Stored(Vec<StoredKey>),
+}
+
+trait AgentStream: Read + Write + Send {}
+
+impl<T: Read + Write + Send> AgentStream for T {}
+
+/// One agent connection from the server. Each request is answered as soon
+/// as it has fully arrived.
+pub struct AgentPeer {
+    backend: Backend,
+    input: Vec<u8>,
+    output: Vec<u8>,
+    closed: bool,
+}
+
+/// Opens the local end of a forwarded agent connection.
+pub fn open(
+    source: AgentSource,
+    storage: &tokio::sync::Mutex<SecureStorage>,
+    passphrases: &PassphraseCache,
+) -> io::Result<AgentPeer> {
+    let backend = match source {
+        AgentSource::Local => Backend::Local(connect_local()?),
+        AgentSource::Stored => Backend::Stored(load_stored(&storage.blocking_lock(), passphrases)),
+    };
+    Ok(AgentPeer {
+        backend,
+        input: Vec::new(),
+        output: Vec::new(),
+        closed: false,
+    })
+}
+
+#[cfg(unix)]
+fn connect_local() -> io::Result<Box<dyn AgentStream>> {
+    let path = std::env::var_os("SSH_AUTH_SOCK")
+        .ok_or_else(|| io::Error::new(ErrorKind::NotFound, "SSH_AUTH_SOCK is not set"))?;
+    Ok(Box::new(std::os::unix::net::UnixStream::connect(path)?))
+}
+
+#[cfg(windows)]
+fn connect_local() -> io::Result<Box<dyn AgentStream>> {
+    let pipe = std::fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(r"\\.\pipe\openssh-ssh-agent")?;
+    Ok(Box::new(pipe))
+}
+
+/// Every stored key that can be used without asking for a passphrase.
+fn load_stored(storage: &SecureStorage, passphrases: &PassphraseCache) -> Vec<StoredKey> {
+    let names: Vec<String> = storage
+        .list_keys()
+        .unwrap_or_default()
+        .iter()
+        .filter_map(|key| key.strip_prefix("ssh_key_").map(str::to_string))
+        .collect();
+
+    names
+        .into_iter()
+        .filter_map(|name| {
+            let pem = storage.retrieve(&format!("ssh_key_{}", name)).ok()?;
+            // Legacy PEM keys can't be loaded for signing
+            let mut key = PrivateKey::from_openssh(&pem).ok()?;
+            if key.is_encrypted() {
+                let passphrase = passphrases.get(&keys::stored_key_label(&name))?;
+                key = key.decrypt(passphrase).ok()?;
+            }
+            let blob = key.public_key().to_bytes().ok()?;
+            Some(StoredKey { name, blob, key })
+        })
+        .collect()
+}
+
+impl AgentPeer {
+    fn answer(&mut self, request: &[u8]) -> Vec<u8> {
+        let reply = match &mut self.backend {
+            Backend::Local(agent) => call_local(agent.as_mut(), request)
+                .map_err(|e| warn!("Local agent request failed: {}", e))
+                .ok(),
+            Backend::Stored(keys) => answer_stored(keys, request),
+        };
+        reply.unwrap_or_else(|| vec![SSH_AGENT_FAILURE])
+    }
+}
+
+impl Peer for AgentPeer {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.output.is_empty() {
+            return if self.closed { Ok(0) } else { Err(ErrorKind::WouldBlock.into()) };
+        }
+        let n = buf.len().min(self.output.len());
+        buf[..n].copy_from_slice(&self.output[..n]);
+        self.output.drain(..n);
+        Ok(n)
+    }
+
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        self.input.extend_from_slice(data);
+        while let Some(mut header) = self.input.get(..4) {
+            let len = take_u32(&mut header).unwrap() as usize;
+            if len > MAX_MESSAGE_LEN {
+                return Err(io::Error::new(ErrorKind::InvalidData, "Agent request too long"));
+            }
+            if self.input.len() < 4 + len {
+                break;
+            }
+            let request: Vec<u8> = self.input.drain(..4 + len).skip(4).collect();
+            let reply = self.answer(&request);
+            put_string(&mut self.output, &reply);
+        }
+        Ok(data.len())
+    }
+
+    fn close_write(&mut self) {
+        self.closed = true;
+    }
+}
+
+/// Passes a request on to the local agent and waits for its reply.
+fn call_local(agent: &mut dyn AgentStream, request: &[u8]) -> io::Result<Vec<u8>> {
+    let mut message = Vec::with_capacity(4 + request.len());
+    put_string(&mut message, request);
+    agent.write_all(&message)?;
+
+    let mut len = [0u8; 4];
+    agent.read_exact(&mut len)?;
+    let len = u32::from_be_bytes(len) as usize;
+    if len > MAX_MESSAGE_LEN {
+        return Err(io::Error::new(ErrorKind::InvalidData, "Agent reply too long"));
+    }
+    let mut reply = vec![0u8; len];
+    agent.read_exact(&mut reply)?;
+    Ok(reply)
+}
+
+/// Lists and signs with stored keys. `None` is answered with a failure; so
+/// is anything else an agent can do, like adding or removing keys.
+fn answer_stored(keys: &[StoredKey], request: &[u8]) -> Option<Vec<u8>> {
+    let (&message, mut body) = request.split_first()?;
+    match message {
+        SSH_AGENTC_REQUEST_IDENTITIES => {
+            let mut reply = vec![SSH_AGENT_IDENTITIES_ANSWER];
+            reply.extend_from_slice(&(keys.len() as u32).to_be_bytes());
+            for key in keys {
+                put_string(&mut reply, &key.blob);
+                put_string(&mut reply, key.name.as_bytes());
+            }
+            Some(reply)
+        }
+        SSH_AGENTC_SIGN_REQUEST => {
+            let blob = take_string(&mut body)?;
+            let data = take_string(&mut body)?;
+            let flags = take_u32(&mut body)?;
+            let key = keys.iter().find(|key| key.blob == blob)?;
+            // ssh-key only signs RSA with SHA-512
+            if key.key.algorithm().is_rsa() && flags & SSH_AGENT_RSA_SHA2_512 == 0 {
+                warn!("Refusing RSA signature without SHA-512 for {}", key.name);
+                return None;
+            }
+
+            let signature = sign(&key.key, data)?;
+            info!("Signed a request from the server with stored key {}", key.name);
+            let algorithm = signature.algorithm();
+            let mut encoded = Vec::new();
+            put_string(&mut encoded, algorithm.as_str().as_bytes());
+            put_string(&mut encoded, signature.as_bytes());
+
+            let mut reply = vec![SSH_AGENT_SIGN_RESPONSE];
+            put_string(&mut reply, &encoded);
+            Some(reply)
+        }
+        _ => None,
+    }
+}
+
+fn sign<K: SigningKey>(key: &K, data: &[u8]) -> Option<Signature> {
+    key.try_sign(data)
+        .map_err(|e| warn!("Signing failed: {}", e))
+        .ok()
+}
+
+fn put_string(buf: &mut Vec<u8>, data: &[u8]) {
+    buf.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    buf.extend_from_slice(data);
+}
+
+fn take_u32(buf: &mut &[u8]) -> Option<u32> {
+    let value = u32::from_be_bytes(buf.get(..4)?.try_into().unwrap());
+    *buf = &buf[4..];
+    Some(value)
+}
+
+fn take_string<'a>(buf: &mut &'a [u8]) -> Option<&'a [u8]> {
+    let len = take_u32(buf)? as usize;
+    let rest = *buf;
+    let data = rest.get(..len)?;
+    *buf = &rest[len..];
+    Some(data)
+}
@@ -0,0 +1,94 @@
+use serde::{Deserialize, Serialize};
+use ssh2::{MethodType, Session};
+
+use crate::ssh::SshError;
+
+/// Algorithms to offer, most preferred first, by their SSH names
+/// (`"curve25519-sha256"`, `"aes256-gcm@openssh.com"`, ...). An empty list
+/// keeps libssh2's defaults; names libssh2 doesn't support are skipped.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct AlgorithmPreferences {
+    #[serde(default)]
+    pub kex: Vec<String>,
+    #[serde(default)]
+    pub host_key: Vec<String>,
+    #[serde(default)]
+    pub cipher: Vec<String>,
+    #[serde(default)]
+    pub mac: Vec<String>,
+    /// `"none"` and/or `"zlib@openssh.com"`, `"zlib"`.
+    #[serde(default)]
+    pub compression: Vec<String>,
+}
+
+impl AlgorithmPreferences {
+    /// Sets the preferences on a session. Must come before the handshake.
+    pub fn apply(&self, session: &Session) -> Result<(), SshError> {
+        // libssh2 only offers zlib on sessions with compression turned on
+        if self.compression.iter().map(|name| name.trim()).any(|name| !name.is_empty() && name != "none") {
+            session.set_compress(true);
+        }
+
+        let lists = [
+            ("key exchange", &self.kex, &[MethodType::Kex][..]),
+            ("host key", &self.host_key, &[MethodType::HostKey][..]),
+            ("cipher", &self.cipher, &[MethodType::CryptCs, MethodType::CryptSc][..]),
+            ("MAC", &self.mac, &[MethodType::MacCs, MethodType::MacSc][..]),
+            ("compression", &self.compression, &[MethodType::CompCs, MethodType::CompSc][..]),
+        ];
+        for (kind, names, method_types) in lists {
+            let prefs = join(names);
+            if prefs.is_empty() {
+                continue;
+            }
+            for &method_type in method_types {
+                session.method_pref(method_type, &prefs)
+                    .map_err(|e| SshError::UnsupportedAlgorithms {
+                        kind,
+                        prefs: prefs.clone(),
+                        reason: e.to_string(),
+                    })?;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn join(names: &[String]) -> String {
+    names
+        .iter()
+        .map(|name| name.trim())
+        .filter(|name| !name.is_empty())
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+/// The algorithms a session settled on in its handshake. `_cs` is client
+/// to server, `_sc` server to client.
+#[derive(Debug, Clone, Serialize)]
+pub struct NegotiatedMethods {
+    pub kex: Option<String>,
+    pub host_key: Option<String>,
+    pub cipher_cs: Option<String>,
+    pub cipher_sc: Option<String>,
+    pub mac_cs: Option<String>,
+    pub mac_sc: Option<String>,
+    pub compression_cs: Option<String>,
+    pub compression_sc: Option<String>,
+}
+
+impl NegotiatedMethods {
+    pub fn of(session: &Session) -> Self {
+        let method = |method_type| session.methods(method_type).map(str::to_string);
+        Self {
+            kex: method(MethodType::Kex),
+            host_key: method(MethodType::HostKey),
+            cipher_cs: method(MethodType::CryptCs),
+            cipher_sc: method(MethodType::CryptSc),
+            mac_cs: method(MethodType::MacCs),
+            mac_sc: method(MethodType::MacSc),
+            compression_cs: method(MethodType::CompCs),
+            compression_sc: method(MethodType::CompSc),
+        }
+    }
+}
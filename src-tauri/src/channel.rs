@@ -0,0 +1,326 @@
+use libssh2_sys as raw;
+use ssh2::{Channel, Error, ExitSignal, Session};
+use std::ffi::{c_void, CString};
+use std::io::{self, Read, Write};
+use std::os::raw::{c_char, c_int, c_uint};
+use std::ptr::null_mut;
+
+extern "C" {
+    // In libssh2.h, but not declared by libssh2-sys
+    fn libssh2_channel_x11_req_ex(
+        channel: *mut raw::LIBSSH2_CHANNEL,
+        single_connection: c_int,
+        auth_proto: *const c_char,
+        auth_cookie: *const c_char,
+        screen_number: c_int,
+    ) -> c_int;
+}
+
+/// What a terminal needs from its channel. `ssh2::Channel` covers ordinary
+/// shells; shells that need requests `ssh2` can't make use a `RawChannel`.
+pub trait ShellChannel: Read + Write + Send {
+    fn request_pty(&mut self, term: &str, size: (u32, u32)) -> Result<(), Error>;
+    fn request_pty_size(&mut self, size: (u32, u32)) -> Result<(), Error>;
+    fn request_auth_agent_forwarding(&mut self) -> Result<(), Error>;
+    fn shell(&mut self) -> Result<(), Error>;
+    fn setenv(&mut self, var: &str, val: &str) -> Result<(), Error>;
+    fn send_eof(&mut self) -> Result<(), Error>;
+    fn wait_eof(&mut self) -> Result<(), Error>;
+    fn close(&mut self) -> Result<(), Error>;
+    fn wait_close(&mut self) -> Result<(), Error>;
+    /// Whether the server has sent EOF on the channel.
+    fn eof(&self) -> bool;
+    fn exit_status(&self) -> Result<i32, Error>;
+    fn exit_signal(&self) -> Result<ExitSignal, Error>;
+}
+
+impl ShellChannel for Channel {
+    fn request_pty(&mut self, term: &str, size: (u32, u32)) -> Result<(), Error> {
+        Channel::request_pty(self, term, None, Some((size.0, size.1, 0, 0)))
+    }
+
+    fn request_pty_size(&mut self, size: (u32, u32)) -> Result<(), Error> {
+        Channel::request_pty_size(self, size.0, size.1, None, None)
+    }
+
+    fn request_auth_agent_forwarding(&mut self) -> Result<(), Error> {
+        Channel::request_auth_agent_forwarding(self)
+    }
+
+    fn shell(&mut self) -> Result<(), Error> {
+        Channel::shell(self)
+    }
+
+    fn setenv(&mut self, var: &str, val: &str) -> Result<(), Error> {
+        Channel::setenv(self, var, val)
+    }
+
+    fn send_eof(&mut self) -> Result<(), Error> {
+        Channel::send_eof(self)
+    }
+
+    fn wait_eof(&mut self) -> Result<(), Error> {
+        Channel::wait_eof(self)
+    }
+
+    fn close(&mut self) -> Result<(), Error> {
+        Channel::close(self)
+    }
+
+    fn wait_close(&mut self) -> Result<(), Error> {
+        Channel::wait_close(self)
+    }
+
+    fn eof(&self) -> bool {
+        Channel::eof(self)
+    }
+
+    fn exit_status(&self) -> Result<i32, Error> {
+        Channel::exit_status(self)
+    }
+
+    fn exit_signal(&self) -> Result<ExitSignal, Error> {
+        Channel::exit_signal(self)
+    }
+}
+
+/// A channel driven through libssh2 directly, for what `ssh2` doesn't wrap:
+/// channels the server opens and X11 requests. As with `ssh2::Channel`,
+/// every call holds the session's internal lock, blocks or not as the
+/// session is set to, and the channel is freed on drop.
+pub struct RawChannel {
+    session: Session,
+    raw: *mut raw::LIBSSH2_CHANNEL,
+}
+
+// Only touched while holding the session's lock, like ssh2's own channels
+unsafe impl Send for RawChannel {}
+
+impl RawChannel {
+    /// Takes ownership of a channel libssh2 opened on `session`.
+    ///
+    /// # Safety
+    /// `raw` must be a live channel of `session` that nothing else frees.
+    pub unsafe fn from_raw(session: &Session, raw: *mut raw::LIBSSH2_CHANNEL) -> Self {
+        Self {
+            session: session.clone(),
+            raw,
+        }
+    }
+
+    /// Opens a `session` channel, as `Session::channel_session` does.
+    pub fn open_session(session: &Session) -> Result<Self, Error> {
+        let mut locked = session.raw();
+        let locked: *mut raw::LIBSSH2_SESSION = &mut *locked;
+        let kind = "session";
+        unsafe {
+            let channel = raw::libssh2_channel_open_ex(
+                locked,
+                kind.as_ptr() as *const c_char,
+                kind.len() as c_uint,
+                raw::LIBSSH2_CHANNEL_WINDOW_DEFAULT as c_uint,
+                raw::LIBSSH2_CHANNEL_PACKET_DEFAULT as c_uint,
+                null_mut(),
+                0,
+            );
+            if channel.is_null() {
+                return Err(Error::last_session_error_raw(locked).unwrap_or_else(Error::unknown));
+            }
+            Ok(Self::from_raw(session, channel))
+        }
+    }
+
+    /// Runs a libssh2 call on the channel and turns a negative return code
+    /// into the session's error.
+    fn call(&self, f: impl FnOnce(*mut raw::LIBSSH2_CHANNEL) -> isize) -> Result<usize, Error> {
+        let mut locked = self.session.raw();
+        let rc = f(self.raw);
+        if rc < 0 {
+            Err(Error::from_session_error_raw(&mut *locked, rc as c_int))
+        } else {
+            Ok(rc as usize)
+        }
+    }
+
+    /// Asks the server to forward X11 connections to us, authenticated with
+    /// `auth_cookie` (hex). Must come before `shell`.
+    pub fn request_x11(&mut self, auth_protocol: &str, auth_cookie: &str, screen: u32) -> Result<(), Error> {
+        let protocol = CString::new(auth_protocol).map_err(|_| Error::unknown())?;
+        let cookie = CString::new(auth_cookie).map_err(|_| Error::unknown())?;
+        self.call(|channel| unsafe {
+            libssh2_channel_x11_req_ex(channel, 0, protocol.as_ptr(), cookie.as_ptr(), screen as c_int) as isize
+        })
+        .map(drop)
+    }
+
+    /// Frees the channel, closing it first if need be. In non-blocking mode
+    /// this returns false while the close is still waiting on the server;
+    /// call again until it returns true.
+    pub fn try_free(&mut self) -> bool {
+        if self.raw.is_null() {
+            return true;
+        }
+        let freed = self.call(|channel| unsafe { raw::libssh2_channel_free(channel) as isize });
+        match freed {
+            Err(e) if e.code() == ssh2::ErrorCode::Session(raw::LIBSSH2_ERROR_EAGAIN) => false,
+            _ => {
+                self.raw = null_mut();
+                true
+            }
+        }
+    }
+}
+
+impl Drop for RawChannel {
+    fn drop(&mut self) {
+        // If the close would block, libssh2 frees the channel with its session
+        self.try_free();
+    }
+}
+
+impl Read for RawChannel {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.eof() {
+            return Ok(0);
+        }
+        let read = self.call(|channel| unsafe {
+            raw::libssh2_channel_read_ex(channel, 0, buf.as_mut_ptr() as *mut c_char, buf.len())
+        })?;
+        Ok(read)
+    }
+}
+
+impl Write for RawChannel {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        let written = self.call(|channel| unsafe {
+            raw::libssh2_channel_write_ex(channel, 0, data.as_ptr() as *const c_char, data.len())
+        })?;
+        Ok(written)
+    }
+
+    /// Written data is sent straight away, so there is nothing to flush.
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl ShellChannel for RawChannel {
+    fn request_pty(&mut self, term: &str, size: (u32, u32)) -> Result<(), Error> {
+        self.call(|channel| unsafe {
+            raw::libssh2_channel_request_pty_ex(
+                channel,
+                term.as_ptr() as *const c_char,
+                term.len() as c_uint,
+                null_mut(),
+                0,
+                size.0 as c_int,
+                size.1 as c_int,
+                0,
+                0,
+            ) as isize
+        })
+        .map(drop)
+    }
+
+    fn request_pty_size(&mut self, size: (u32, u32)) -> Result<(), Error> {
+        self.call(|channel| unsafe {
+            raw::libssh2_channel_request_pty_size_ex(channel, size.0 as c_int, size.1 as c_int, 0, 0) as isize
+        })
+        .map(drop)
+    }
+
+    fn request_auth_agent_forwarding(&mut self) -> Result<(), Error> {
+        self.call(|channel| unsafe { raw::libssh2_channel_request_auth_agent(channel) as isize })
+            .map(drop)
+    }
+
+    fn shell(&mut self) -> Result<(), Error> {
+        let request = "shell";
+        self.call(|channel| unsafe {
+            raw::libssh2_channel_process_startup(
+                channel,
+                request.as_ptr() as *const c_char,
+                request.len() as c_uint,
+                null_mut(),
+                0,
+            ) as isize
+        })
+        .map(drop)
+    }
+
+    fn setenv(&mut self, var: &str, val: &str) -> Result<(), Error> {
+        self.call(|channel| unsafe {
+            raw::libssh2_channel_setenv_ex(
+                channel,
+                var.as_ptr() as *const c_char,
+                var.len() as c_uint,
+                val.as_ptr() as *const c_char,
+                val.len() as c_uint,
+            ) as isize
+        })
+        .map(drop)
+    }
+
+    fn send_eof(&mut self) -> Result<(), Error> {
+        self.call(|channel| unsafe { raw::libssh2_channel_send_eof(channel) as isize }).map(drop)
+    }
+
+    fn wait_eof(&mut self) -> Result<(), Error> {
+        self.call(|channel| unsafe { raw::libssh2_channel_wait_eof(channel) as isize }).map(drop)
+    }
+
+    fn close(&mut self) -> Result<(), Error> {
+        self.call(|channel| unsafe { raw::libssh2_channel_close(channel) as isize }).map(drop)
+    }
+
+    fn wait_close(&mut self) -> Result<(), Error> {
+        self.call(|channel| unsafe { raw::libssh2_channel_wait_closed(channel) as isize }).map(drop)
+    }
+
+    fn eof(&self) -> bool {
+        let _locked = self.session.raw();
+        unsafe { raw::libssh2_channel_eof(self.raw) != 0 }
+    }
+
+    fn exit_status(&self) -> Result<i32, Error> {
+        let _locked = self.session.raw();
+        Ok(unsafe { raw::libssh2_channel_get_exit_status(self.raw) })
+    }
+
+    fn exit_signal(&self) -> Result<ExitSignal, Error> {
+        let mut locked = self.session.raw();
+        let locked: *mut raw::LIBSSH2_SESSION = &mut *locked;
+        unsafe {
+            let mut signal = (null_mut(), 0);
+            let mut message = (null_mut(), 0);
+            let mut lang = (null_mut(), 0);
+            let rc = raw::libssh2_channel_get_exit_signal(
+                self.raw,
+                &mut signal.0,
+                &mut signal.1,
+                &mut message.0,
+                &mut message.1,
+                &mut lang.0,
+                &mut lang.1,
+            );
+            if rc < 0 {
+                return Err(Error::from_session_error_raw(locked, rc));
+            }
+
+            // Copied out and handed back to libssh2's allocator
+            let take = |(ptr, len): (*mut c_char, usize)| {
+                if ptr.is_null() {
+                    return None;
+                }
+                let bytes = std::slice::from_raw_parts(ptr as *const u8, len).to_vec();
+                raw::libssh2_free(locked, ptr as *mut c_void);
+                String::from_utf8(bytes).ok()
+            };
+            Ok(ExitSignal {
+                exit_signal: take(signal),
+                error_message: take(message),
+                lang_tag: take(lang),
+            })
+        }
+    }
+}
@@ -0,0 +1,72 @@
+use encoding_rs::{Decoder, Encoding, EncoderResult, UTF_8};
+
+use crate::ssh::SshError;
+
+/// Looks up a remote charset by any of its WHATWG labels (`"utf-8"`,
+/// `"latin1"`, `"shift_jis"`, ...). `None` or an empty label means UTF-8.
+pub fn lookup(label: Option<&str>) -> Result<&'static Encoding, SshError> {
+    match label.map(str::trim).filter(|label| !label.is_empty()) {
+        Some(label) => Encoding::for_label(label.as_bytes())
+            .ok_or_else(|| SshError::UnknownCharset(label.to_string())),
+        None => Ok(UTF_8),
+    }
+}
+
+/// Turns a shell's output into text chunk by chunk. A multi-byte sequence
+/// split across reads is held back until the rest of it arrives, rather than
+/// being replaced on both sides of the split.
+pub struct OutputDecoder {
+    decoder: Decoder,
+}
+
+impl OutputDecoder {
+    pub fn new(encoding: &'static Encoding) -> Self {
+        Self {
+            decoder: encoding.new_decoder_without_bom_handling(),
+        }
+    }
+
+    pub fn decode(&mut self, bytes: &[u8]) -> String {
+        let capacity = self.decoder
+            .max_utf8_buffer_length(bytes.len())
+            .unwrap_or(bytes.len() * 3);
+        let mut text = String::with_capacity(capacity);
+        // With room for the worst case, the whole input is always consumed
+        let _ = self.decoder.decode_to_string(bytes, &mut text, false);
+        text
+    }
+
+    /// Ends the stream: a character still held back is incomplete for good
+    /// and comes out as U+FFFD.
+    pub fn finish(&mut self) -> String {
+        let capacity = self.decoder.max_utf8_buffer_length(0).unwrap_or(4);
+        let mut text = String::with_capacity(capacity);
+        let _ = self.decoder.decode_to_string(&[], &mut text, true);
+        text
+    }
+}
+
+/// Encodes terminal input for the remote charset. Characters it can't
+/// represent are sent as `?`.
+pub fn encode(encoding: &'static Encoding, text: &str) -> Vec<u8> {
+    if encoding == UTF_8 {
+        return text.as_bytes().to_vec();
+    }
+
+    let mut encoder = encoding.new_encoder();
+    let mut output = Vec::with_capacity(text.len() * 2);
+    let mut remaining = text;
+    loop {
+        let needed = encoder
+            .max_buffer_length_from_utf8_without_replacement(remaining.len())
+            .unwrap_or(remaining.len() * 4);
+        output.reserve(needed);
+        let (result, read) = encoder.encode_from_utf8_to_vec_without_replacement(remaining, &mut output, true);
+        remaining = &remaining[read..];
+        match result {
+            EncoderResult::InputEmpty => return output,
+            EncoderResult::OutputFull => {}
+            EncoderResult::Unmappable(_) => output.push(b'?'),
+        }
+    }
+}
@@ -2,6 +2,16 @@ use serde::{Deserialize, Serialize};
 use std::path::PathBuf;
 use anyhow::Result;
 
+use crate::crypto::SecureStorage;
+
+/// `SecureStorage` key of the app-wide proxy's password.
+pub const PROXY_PASSWORD_KEY: &str = "proxy_password";
+
+/// `SecureStorage` key of the password of a saved session's own proxy.
+pub fn session_proxy_password_key(session_name: &str) -> String {
+    format!("{}_{}", PROXY_PASSWORD_KEY, session_name)
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct AppConfig {
     pub theme: String,
@@ -13,6 +23,17 @@ pub struct AppConfig {
     pub scrollback_lines: u32,
     pub window_opacity: f64,
     pub security: SecurityConfig,
+    #[serde(default)]
+    pub transfers: TransferConfig,
+    #[serde(default)]
+    pub keepalive: KeepaliveConfig,
+    #[serde(default)]
+    pub reconnect: ReconnectConfig,
+    #[serde(default)]
+    pub timeouts: TimeoutConfig,
+    /// Proxy for sessions that don't set their own; direct when unset.
+    #[serde(default)]
+    pub proxy: Option<ProxyConfig>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -20,10 +41,127 @@ pub struct SecurityConfig {
     pub auto_lock_timeout: u32,
     pub require_password_on_startup: bool,
     pub ssh_key_passphrase_cache: bool,
+    /// How long a cached key passphrase stays valid, in seconds.
+    #[serde(default = "default_passphrase_cache_ttl")]
+    pub ssh_key_passphrase_cache_ttl: u32,
     pub verify_host_keys: bool,
     pub strict_host_key_checking: bool,
 }
 
+fn default_passphrase_cache_ttl() -> u32 {
+    900
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct KeepaliveConfig {
+    /// Seconds between keepalives on an idle connection; 0 turns them off.
+    pub interval_secs: u32,
+    /// Consecutive intervals without a reply before the connection is declared lost.
+    pub max_missed: u32,
+}
+
+/// What to do when a live session drops. Delays double after every failed
+/// attempt, starting at `initial_delay_secs` and capped at `max_delay_secs`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReconnectConfig {
+    pub enabled: bool,
+    pub max_attempts: u32,
+    pub initial_delay_secs: u32,
+    pub max_delay_secs: u32,
+}
+
+/// Limits on how long each phase of opening a connection may take, in
+/// seconds; 0 waits indefinitely. Each jump host gets the same limits.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TimeoutConfig {
+    /// Establishing the TCP connection, across all resolved addresses.
+    pub connect_secs: u32,
+    /// The SSH handshake, and each authentication attempt. Keyboard-interactive
+    /// authentication is exempt, since its prompts wait on the user mid-call;
+    /// host key and passphrase prompts are not counted.
+    pub handshake_secs: u32,
+}
+
+/// An outbound proxy the SSH connection is tunnelled through.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ProxyConfig {
+    pub kind: ProxyKind,
+    pub host: String,
+    pub port: u16,
+    /// Sent as Basic auth to HTTP proxies, as RFC 1929 auth to SOCKS5 ones.
+    #[serde(default)]
+    pub username: Option<String>,
+    /// Only ever held in memory; on disk it lives in `SecureStorage`.
+    #[serde(default, skip_serializing)]
+    pub password: Option<String>,
+    /// Where the password is kept in `SecureStorage`, once stored.
+    #[serde(default)]
+    pub password_key: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ProxyKind {
+    /// An HTTP proxy that allows `CONNECT` to the SSH port.
+    Http,
+    Socks5,
+}
+
+impl ProxyConfig {
+    /// Username and password, when a username is set.
+    pub fn credentials(&self) -> Option<(&str, &str)> {
+        let username = self.username.as_deref().filter(|u| !u.is_empty())?;
+        Some((username, self.password.as_deref().unwrap_or("")))
+    }
+
+    /// Moves the password into `storage` under `key`, keeping only the key.
+    /// With no new password entered, the one stored under `password_key` is
+    /// carried over. Without a username nothing is stored.
+    pub fn store_password(&mut self, storage: &SecureStorage, key: &str) -> Result<()> {
+        let password = match self.password.take().filter(|p| !p.is_empty()) {
+            Some(password) => Some(password),
+            None => self.stored_password(storage).ok().flatten(),
+        };
+        self.password_key = None;
+        match password.filter(|_| self.username.as_deref().is_some_and(|u| !u.is_empty())) {
+            Some(password) => {
+                storage.store(key, &password)?;
+                self.password_key = Some(key.to_string());
+            }
+            None => {
+                storage.delete(key).ok();
+            }
+        }
+        Ok(())
+    }
+
+    /// Fills in the password from `storage`, unless one has been entered
+    /// since it was stored.
+    pub fn load_password(&mut self, storage: &SecureStorage) -> Result<()> {
+        if self.password.as_deref().unwrap_or_default().is_empty() {
+            if let Some(password) = self.stored_password(storage)? {
+                self.password = Some(password);
+            }
+        }
+        Ok(())
+    }
+
+    /// The password `password_key` points at. Only proxy password keys are read.
+    fn stored_password(&self, storage: &SecureStorage) -> Result<Option<String>> {
+        match self.password_key.as_deref().filter(|key| key.starts_with(PROXY_PASSWORD_KEY)) {
+            Some(key) => storage.retrieve(key).map(Some),
+            None => Ok(None),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TransferConfig {
+    /// File transfers run at once per connection, shared by all its tabs; the
+    /// rest wait in the queue.
+    pub max_concurrent: usize,
+}
+
 impl Default for AppConfig {
     fn default() -> Self {
         Self {
@@ -36,6 +174,11 @@ impl Default for AppConfig {
             scrollback_lines: 10000,
             window_opacity: 1.0,
             security: SecurityConfig::default(),
+            transfers: TransferConfig::default(),
+            keepalive: KeepaliveConfig::default(),
+            reconnect: ReconnectConfig::default(),
+            timeouts: TimeoutConfig::default(),
+            proxy: None,
         }
     }
 }
@@ -46,12 +189,50 @@ impl Default for SecurityConfig {
             auto_lock_timeout: 300,
             require_password_on_startup: false,
             ssh_key_passphrase_cache: false,
+            ssh_key_passphrase_cache_ttl: default_passphrase_cache_ttl(),
             verify_host_keys: true,
             strict_host_key_checking: true,
         }
     }
 }
 
+impl Default for KeepaliveConfig {
+    fn default() -> Self {
+        Self {
+            interval_secs: 30,
+            max_missed: 3,
+        }
+    }
+}
+
+impl Default for ReconnectConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            max_attempts: 5,
+            initial_delay_secs: 1,
+            max_delay_secs: 60,
+        }
+    }
+}
+
+impl Default for TimeoutConfig {
+    fn default() -> Self {
+        Self {
+            connect_secs: 15,
+            handshake_secs: 30,
+        }
+    }
+}
+
+impl Default for TransferConfig {
+    fn default() -> Self {
+        Self {
+            max_concurrent: 3,
+        }
+    }
+}
+
 pub struct ConfigManager {
     config: AppConfig,
     config_path: PathBuf,
@@ -68,6 +249,13 @@ impl ConfigManager {
         })
     }
 
+    pub fn new_default() -> Self {
+        Self {
+            config: AppConfig::default(),
+            config_path: Self::get_config_path(),
+        }
+    }
+
     fn get_config_path() -> PathBuf {
         if let Some(config_dir) = directories::ProjectDirs::from("com", "sshterminal", "app") {
             config_dir.config_dir().join("config.json")
@@ -0,0 +1,122 @@
+use serde::Serialize;
+use ssh2::{Channel, Session};
+use std::io::{ErrorKind, Read};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::ssh::SshError;
+
+const IDLE_POLL_MIN: Duration = Duration::from_millis(2);
+const IDLE_POLL_MAX: Duration = Duration::from_millis(50);
+
+/// Outcome of a command run without a PTY.
+#[derive(Debug, Clone, Serialize)]
+pub struct ExecResult {
+    pub stdout: String,
+    pub stderr: String,
+    pub exit_status: i32,
+    /// Set when the command was killed by a signal, e.g. `"KILL"`.
+    pub exit_signal: Option<String>,
+}
+
+/// Runs `command` on a new exec channel and collects its output.
+///
+/// Like `tunnel::relay`, every libssh2 call is made while holding `lock`, so
+/// the session can stay shared with a live terminal. Fails with
+/// `ExecTimedOut` if the command hasn't finished within `timeout`.
+pub fn run<T>(
+    session: &Session,
+    lock: &Mutex<T>,
+    command: &str,
+    timeout: Duration,
+) -> Result<ExecResult, SshError> {
+    let deadline = Instant::now() + timeout;
+
+    let mut channel = {
+        let _guard = lock.lock().unwrap();
+        let mut channel = session.channel_session()
+            .map_err(|e| SshError::ChannelFailed(e.to_string()))?;
+        channel.exec(command)
+            .map_err(|e| SshError::ExecFailed(e.to_string()))?;
+        channel
+    };
+
+    let output = read_output(session, lock, &mut channel, deadline, timeout);
+
+    let _guard = lock.lock().unwrap();
+    channel.close().ok();
+    let result = output.and_then(|(stdout, stderr)| {
+        channel.wait_close().ok();
+        let exit_status = channel.exit_status()
+            .map_err(|e| SshError::ExecFailed(e.to_string()))?;
+        let exit_signal = channel.exit_signal()
+            .ok()
+            .and_then(|signal| signal.exit_signal);
+
+        Ok(ExecResult {
+            stdout: String::from_utf8_lossy(&stdout).into_owned(),
+            stderr: String::from_utf8_lossy(&stderr).into_owned(),
+            exit_status,
+            exit_signal,
+        })
+    });
+    // Freeing the channel is a libssh2 call too, so it happens before the guard goes
+    drop(channel);
+    result
+}
+
+/// Reads stdout and stderr from `channel` until EOF or `deadline`.
+fn read_output<T>(
+    session: &Session,
+    lock: &Mutex<T>,
+    channel: &mut Channel,
+    deadline: Instant,
+    timeout: Duration,
+) -> Result<(Vec<u8>, Vec<u8>), SshError> {
+    let mut buffer = [0u8; 8192];
+    let mut stdout = Vec::new();
+    let mut stderr = Vec::new();
+    let mut idle_poll = IDLE_POLL_MIN;
+
+    loop {
+        let (progressed, eof) = {
+            let _guard = lock.lock().unwrap();
+            session.set_blocking(false);
+            let result = (|| {
+                // Checked before draining so nothing that arrived with the EOF is left behind
+                let eof = channel.eof();
+                let mut progressed = false;
+                for (stream_id, output) in [(0, &mut stdout), (1, &mut stderr)] {
+                    loop {
+                        match channel.stream(stream_id).read(&mut buffer) {
+                            Ok(0) => break,
+                            Ok(n) => {
+                                output.extend_from_slice(&buffer[..n]);
+                                progressed = true;
+                            }
+                            Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+                            Err(e) => return Err(SshError::ReadFailed(e.to_string())),
+                        }
+                    }
+                }
+                Ok((progressed, eof))
+            })();
+            session.set_blocking(true);
+            result?
+        };
+
+        if eof {
+            return Ok((stdout, stderr));
+        }
+        if Instant::now() >= deadline {
+            return Err(SshError::ExecTimedOut(timeout.as_secs()));
+        }
+
+        if progressed {
+            idle_poll = IDLE_POLL_MIN;
+        } else {
+            std::thread::sleep(idle_poll);
+            idle_poll = (idle_poll * 2).min(IDLE_POLL_MAX);
+        }
+    }
+}
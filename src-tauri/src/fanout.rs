@@ -0,0 +1,196 @@
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tauri::{AppHandle, Emitter};
+use tokio::sync::Semaphore;
+use log::{info, warn};
+
+use crate::ssh::{ConnectParams, SshManager};
+
+/// A saved session to run a fan-out command on, with credentials decrypted.
+pub struct FanoutTarget {
+    pub name: String,
+    pub params: ConnectParams,
+}
+
+/// One host's outcome. `error` is set when the command couldn't be run at
+/// all (connect, auth or timeout); a non-zero exit status is not an error.
+#[derive(Debug, Clone, Serialize)]
+pub struct HostResult {
+    pub session_name: String,
+    pub host: String,
+    pub exit_status: Option<i32>,
+    pub exit_signal: Option<String>,
+    pub stdout: String,
+    pub stderr: String,
+    pub duration_ms: u64,
+    pub error: Option<String>,
+}
+
+/// A fan-out run as exported to JSON.
+#[derive(Debug, Clone, Serialize)]
+pub struct FanoutRun {
+    pub id: String,
+    pub command: String,
+    /// Seconds since the Unix epoch.
+    pub started_at: u64,
+    pub hosts: Vec<String>,
+    pub finished: bool,
+    pub results: Vec<HostResult>,
+}
+
+#[derive(Clone, Serialize)]
+struct ResultEvent {
+    run_id: String,
+    result: HostResult,
+}
+
+#[derive(Clone, Serialize)]
+struct FinishedEvent {
+    run_id: String,
+}
+
+/// Runs one command on many saved sessions, each over its own short-lived
+/// connection, and keeps the results for export.
+///
+/// Every host result is emitted as a `fanout-result` event as soon as it is
+/// in; `fanout-finished` follows the last one.
+pub struct FanoutManager {
+    app: AppHandle,
+    runs: Mutex<HashMap<String, FanoutRun>>,
+}
+
+impl FanoutManager {
+    pub fn new(app: AppHandle) -> Self {
+        Self {
+            app,
+            runs: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Starts running `command` on `targets`, at most `concurrency` at a time.
+    /// Returns the run ID immediately.
+    pub fn start(
+        self: &Arc<Self>,
+        ssh_manager: Arc<SshManager>,
+        targets: Vec<FanoutTarget>,
+        command: String,
+        concurrency: usize,
+        timeout: Duration,
+    ) -> String {
+        let run_id = uuid::Uuid::new_v4().to_string();
+        let run = FanoutRun {
+            id: run_id.clone(),
+            command: command.clone(),
+            started_at: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or(0),
+            hosts: targets.iter().map(|t| t.name.clone()).collect(),
+            finished: false,
+            results: Vec::new(),
+        };
+        self.runs.lock().unwrap().insert(run_id.clone(), run);
+
+        info!("Fan-out {} of {:?} on {} sessions", run_id, command, targets.len());
+        let manager = self.clone();
+        let id = run_id.clone();
+        tokio::spawn(async move {
+            let permits = Arc::new(Semaphore::new(concurrency.max(1)));
+            let mut tasks = tokio::task::JoinSet::new();
+
+            for target in targets {
+                let permits = permits.clone();
+                let ssh_manager = ssh_manager.clone();
+                let command = command.clone();
+                let manager = manager.clone();
+                let run_id = id.clone();
+
+                tasks.spawn(async move {
+                    let Ok(_permit) = permits.acquire_owned().await else {
+                        return;
+                    };
+                    let result = tokio::task::spawn_blocking(move || {
+                        run_on_host(&ssh_manager, target, &command, timeout)
+                    })
+                    .await;
+
+                    match result {
+                        Ok(result) => manager.record(&run_id, result),
+                        Err(e) => warn!("Fan-out task failed: {}", e),
+                    }
+                });
+            }
+
+            while tasks.join_next().await.is_some() {}
+
+            if let Some(run) = manager.runs.lock().unwrap().get_mut(&id) {
+                run.finished = true;
+            }
+            info!("Fan-out {} finished", id);
+            manager.app.emit("fanout-finished", FinishedEvent { run_id: id }).ok();
+        });
+
+        run_id
+    }
+
+    fn record(&self, run_id: &str, result: HostResult) {
+        if let Some(run) = self.runs.lock().unwrap().get_mut(run_id) {
+            run.results.push(result.clone());
+        }
+
+        let event = ResultEvent {
+            run_id: run_id.to_string(),
+            result,
+        };
+        if let Err(e) = self.app.emit("fanout-result", event) {
+            warn!("Failed to emit fan-out result for {}: {}", run_id, e);
+        }
+    }
+
+    pub fn get(&self, run_id: &str) -> Option<FanoutRun> {
+        self.runs.lock().unwrap().get(run_id).cloned()
+    }
+
+    /// The run and all results so far, as pretty-printed JSON.
+    pub fn export_json(&self, run_id: &str) -> Option<String> {
+        self.get(run_id).and_then(|run| serde_json::to_string_pretty(&run).ok())
+    }
+
+    pub fn remove(&self, run_id: &str) {
+        self.runs.lock().unwrap().remove(run_id);
+    }
+}
+
+fn run_on_host(
+    ssh_manager: &SshManager,
+    target: FanoutTarget,
+    command: &str,
+    timeout: Duration,
+) -> HostResult {
+    let started = Instant::now();
+    let outcome = ssh_manager.exec_detached(&target.params, command, timeout);
+    let duration_ms = started.elapsed().as_millis() as u64;
+
+    let mut result = HostResult {
+        session_name: target.name,
+        host: target.params.config.host,
+        exit_status: None,
+        exit_signal: None,
+        stdout: String::new(),
+        stderr: String::new(),
+        duration_ms,
+        error: None,
+    };
+    match outcome {
+        Ok(exec) => {
+            result.exit_status = Some(exec.exit_status);
+            result.exit_signal = exec.exit_signal;
+            result.stdout = exec.stdout;
+            result.stderr = exec.stderr;
+        }
+        Err(e) => result.error = Some(e.to_string()),
+    }
+    result
+}
@@ -0,0 +1,443 @@
+use serde::Serialize;
+use std::io::{self, ErrorKind};
+use std::net::{TcpListener, TcpStream};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use log::{info, warn};
+
+use ssh2::{Channel, Session};
+
+use crate::socks::{self, Reply};
+use crate::ssh::{SshConnection, SshError};
+use crate::tunnel::{self, RelayControl};
+
+/// How often an idle listener checks whether its forward was stopped.
+const ACCEPT_POLL: Duration = Duration::from_millis(50);
+/// How long a SOCKS client gets to send its request.
+const SOCKS_HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Clone, Copy, Serialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ForwardKind {
+    Local,
+    Remote,
+    Dynamic,
+}
+
+/// Snapshot of a forward for the UI.
+#[derive(Debug, Clone, Serialize)]
+pub struct ForwardInfo {
+    pub id: String,
+    pub session_id: String,
+    pub kind: ForwardKind,
+    pub bind_host: String,
+    pub bind_port: u16,
+    pub target_host: Option<String>,
+    pub target_port: Option<u16>,
+    pub active_connections: usize,
+    pub total_connections: u64,
+    pub bytes_sent: u64,
+    pub bytes_received: u64,
+}
+
+#[derive(Default)]
+struct ForwardStats {
+    relay: RelayControl,
+    active_connections: AtomicUsize,
+    total_connections: AtomicU64,
+}
+
+/// A running forward. Its threads keep going until `stop` is called.
+pub struct Forward {
+    info: ForwardInfo,
+    stats: Arc<ForwardStats>,
+    connection: Arc<Mutex<SshConnection>>,
+}
+
+impl Forward {
+    fn new(
+        session_id: &str,
+        connection: &Arc<Mutex<SshConnection>>,
+        kind: ForwardKind,
+        bind_host: &str,
+        bind_port: u16,
+        target: Option<(&str, u16)>,
+    ) -> Self {
+        Self {
+            info: ForwardInfo {
+                id: uuid::Uuid::new_v4().to_string(),
+                session_id: session_id.to_string(),
+                kind,
+                bind_host: bind_host.to_string(),
+                bind_port,
+                target_host: target.map(|(host, _)| host.to_string()),
+                target_port: target.map(|(_, port)| port),
+                active_connections: 0,
+                total_connections: 0,
+                bytes_sent: 0,
+                bytes_received: 0,
+            },
+            stats: Arc::new(ForwardStats::default()),
+            connection: connection.clone(),
+        }
+    }
+
+    pub fn id(&self) -> &str {
+        &self.info.id
+    }
+
+    pub fn session_id(&self) -> &str {
+        &self.info.session_id
+    }
+
+    /// The connection the forward's channels are opened on.
+    pub fn connection(&self) -> &Arc<Mutex<SshConnection>> {
+        &self.connection
+    }
+
+    pub fn info(&self) -> ForwardInfo {
+        ForwardInfo {
+            active_connections: self.stats.active_connections.load(Ordering::Relaxed),
+            total_connections: self.stats.total_connections.load(Ordering::Relaxed),
+            bytes_sent: self.stats.relay.bytes_sent.load(Ordering::Relaxed),
+            bytes_received: self.stats.relay.bytes_received.load(Ordering::Relaxed),
+            ..self.info.clone()
+        }
+    }
+
+    /// Closes the listener and every connection relayed through it.
+    pub fn stop(&self) {
+        self.stats.relay.stop.store(true, Ordering::Relaxed);
+    }
+}
+
+/// Binds `bind_host:bind_port` locally and relays each accepted connection to
+/// `target_host:target_port` over a `direct-tcpip` channel on `connection`.
+pub fn start_local(
+    session_id: &str,
+    connection: Arc<Mutex<SshConnection>>,
+    bind_host: &str,
+    bind_port: u16,
+    target_host: &str,
+    target_port: u16,
+) -> Result<Forward, SshError> {
+    let (listener, bound_port) = bind_local(bind_host, bind_port)?;
+    let forward = Forward::new(
+        session_id,
+        &connection,
+        ForwardKind::Local,
+        bind_host,
+        bound_port,
+        Some((target_host, target_port)),
+    );
+
+    let target_host = target_host.to_string();
+    info!("Forwarding {} -> {}:{}", bound_port, target_host, target_port);
+    spawn_accept_loop(listener, bound_port, forward.stats.clone(), move |stream, stats| {
+        relay_to_target(&connection, stream, &target_host, target_port, &stats);
+    })?;
+
+    Ok(forward)
+}
+
+/// Runs a SOCKS5 proxy on `bind_host:bind_port`, opening a `direct-tcpip`
+/// channel on `connection` to whatever destination each client asks for.
+pub fn start_dynamic(
+    session_id: &str,
+    connection: Arc<Mutex<SshConnection>>,
+    bind_host: &str,
+    bind_port: u16,
+) -> Result<Forward, SshError> {
+    let (listener, bound_port) = bind_local(bind_host, bind_port)?;
+    let forward = Forward::new(session_id, &connection, ForwardKind::Dynamic, bind_host, bound_port, None);
+
+    info!("SOCKS5 proxy on port {}", bound_port);
+    spawn_accept_loop(listener, bound_port, forward.stats.clone(), move |stream, stats| {
+        relay_socks(&connection, stream, &stats);
+    })?;
+
+    Ok(forward)
+}
+
+fn bind_local(bind_host: &str, bind_port: u16) -> Result<(TcpListener, u16), SshError> {
+    let listener = TcpListener::bind((bind_host, bind_port))
+        .map_err(|e| SshError::ForwardFailed(format!("Failed to bind {}:{}: {}", bind_host, bind_port, e)))?;
+    listener.set_nonblocking(true)
+        .map_err(|e| SshError::ForwardFailed(e.to_string()))?;
+    let bound_port = listener.local_addr()
+        .map_err(|e| SshError::ForwardFailed(e.to_string()))?
+        .port();
+    Ok((listener, bound_port))
+}
+
+/// Accepts connections on `listener` until the forward is stopped, handing
+/// each one to `handle` on its own thread.
+fn spawn_accept_loop<F>(
+    listener: TcpListener,
+    bound_port: u16,
+    stats: Arc<ForwardStats>,
+    handle: F,
+) -> Result<(), SshError>
+where
+    F: Fn(TcpStream, Arc<ForwardStats>) + Send + Sync + 'static,
+{
+    let handle = Arc::new(handle);
+    std::thread::Builder::new()
+        .name(format!("ssh-forward-{}", bound_port))
+        .spawn(move || {
+            while !stats.relay.is_stopped() {
+                match listener.accept() {
+                    Ok((stream, _)) => {
+                        let handle = handle.clone();
+                        let stats = stats.clone();
+                        std::thread::spawn(move || handle(stream, stats));
+                    }
+                    Err(e) if e.kind() == ErrorKind::WouldBlock => std::thread::sleep(ACCEPT_POLL),
+                    Err(e) => {
+                        warn!("Forward on port {} stopped accepting: {}", bound_port, e);
+                        break;
+                    }
+                }
+            }
+            info!("Forward on port {} closed", bound_port);
+        })
+        .map_err(|e| SshError::ForwardFailed(format!("Failed to start listener: {}", e)))?;
+    Ok(())
+}
+
+/// Asks the server to listen on `bind_host:bind_port` and relays each
+/// connection it receives to `target_host:target_port` on this machine.
+pub fn start_remote(
+    session_id: &str,
+    connection: Arc<Mutex<SshConnection>>,
+    bind_host: Option<&str>,
+    bind_port: u16,
+    target_host: &str,
+    target_port: u16,
+) -> Result<Forward, SshError> {
+    let (mut listener, bound_port) = {
+        let conn = connection.lock().unwrap();
+        conn.session()
+            .channel_forward_listen(bind_port, bind_host, None)
+            .map_err(|e| SshError::ForwardFailed(format!("Server refused to listen on port {}: {}", bind_port, e)))?
+    };
+
+    let forward = Forward::new(
+        session_id,
+        &connection,
+        ForwardKind::Remote,
+        bind_host.unwrap_or(""),
+        bound_port,
+        Some((target_host, target_port)),
+    );
+
+    let stats = forward.stats.clone();
+    let target_host = target_host.to_string();
+    std::thread::Builder::new()
+        .name(format!("ssh-rforward-{}", bound_port))
+        .spawn(move || {
+            info!("Remote forwarding {} -> {}:{}", bound_port, target_host, target_port);
+            while !stats.relay.is_stopped() {
+                let accepted = {
+                    let conn = connection.lock().unwrap();
+                    let session = conn.session();
+                    session.set_blocking(false);
+                    let accepted = listener.accept()
+                        .map(|channel| (session.clone(), channel))
+                        .map_err(std::io::Error::from);
+                    session.set_blocking(true);
+                    accepted
+                };
+
+                match accepted {
+                    Ok((session, channel)) => {
+                        let connection = connection.clone();
+                        let stats = stats.clone();
+                        let target_host = target_host.clone();
+                        std::thread::spawn(move || {
+                            relay_to_local(&connection, session, channel, &target_host, target_port, &stats);
+                        });
+                    }
+                    Err(e) if e.kind() == ErrorKind::WouldBlock => std::thread::sleep(ACCEPT_POLL),
+                    Err(e) => {
+                        warn!("Remote forward on port {} stopped accepting: {}", bound_port, e);
+                        break;
+                    }
+                }
+            }
+
+            // Dropping the listener cancels the forward on the server
+            let _conn = connection.lock().unwrap();
+            drop(listener);
+            info!("Remote forward on port {} closed", bound_port);
+        })
+        .map_err(|e| SshError::ForwardFailed(format!("Failed to start listener: {}", e)))?;
+
+    Ok(forward)
+}
+
+fn relay_to_local(
+    connection: &Mutex<SshConnection>,
+    session: Session,
+    mut channel: Channel,
+    target_host: &str,
+    target_port: u16,
+    stats: &ForwardStats,
+) {
+    stats.total_connections.fetch_add(1, Ordering::Relaxed);
+    stats.active_connections.fetch_add(1, Ordering::Relaxed);
+
+    match TcpStream::connect((target_host, target_port)) {
+        Ok(stream) => {
+            if let Err(e) = tunnel::relay(&session, &mut channel, stream, connection, &stats.relay) {
+                warn!("Remote-forwarded connection to {}:{} failed: {}", target_host, target_port, e);
+            }
+            // Freeing the channel is a libssh2 call too
+            let _conn = connection.lock().unwrap();
+            drop(channel);
+        }
+        Err(e) => {
+            warn!("Failed to connect to {}:{}: {}", target_host, target_port, e);
+            let _conn = connection.lock().unwrap();
+            channel.close().ok();
+            drop(channel);
+        }
+    }
+
+    stats.active_connections.fetch_sub(1, Ordering::Relaxed);
+}
+
+/// Runs the SOCKS handshake on an accepted client, allowing it
+/// `SOCKS_HANDSHAKE_TIMEOUT`.
+fn read_socks_request(stream: &mut TcpStream) -> io::Result<(String, u16)> {
+    // On macOS, BSD and Windows accepted sockets inherit the listener's
+    // non-blocking mode, which would fail the first read at once
+    stream.set_nonblocking(false)?;
+    stream.set_read_timeout(Some(SOCKS_HANDSHAKE_TIMEOUT))?;
+    let target = socks::accept_connect(stream)?;
+    stream.set_read_timeout(None)?;
+    Ok(target)
+}
+
+fn relay_to_target(
+    connection: &Mutex<SshConnection>,
+    stream: TcpStream,
+    target_host: &str,
+    target_port: u16,
+    stats: &ForwardStats,
+) {
+    stats.total_connections.fetch_add(1, Ordering::Relaxed);
+    stats.active_connections.fetch_add(1, Ordering::Relaxed);
+
+    let origin = stream.peer_addr().ok();
+    let opened = {
+        let conn = connection.lock().unwrap();
+        let session = conn.session();
+        let source = origin.as_ref().map(|addr| (addr.ip().to_string(), addr.port()));
+        session
+            .channel_direct_tcpip(target_host, target_port, source.as_ref().map(|(ip, port)| (ip.as_str(), *port)))
+            .map(|channel| (session, channel))
+    };
+
+    match opened {
+        Ok((session, mut channel)) => {
+            if let Err(e) = tunnel::relay(&session, &mut channel, stream, connection, &stats.relay) {
+                warn!("Forwarded connection to {}:{} failed: {}", target_host, target_port, e);
+            }
+            // Freeing the channel is a libssh2 call too
+            let _conn = connection.lock().unwrap();
+            drop(channel);
+        }
+        Err(e) => warn!("direct-tcpip to {}:{} refused: {}", target_host, target_port, e),
+    }
+
+    stats.active_connections.fetch_sub(1, Ordering::Relaxed);
+}
+
+fn relay_socks(connection: &Mutex<SshConnection>, mut stream: TcpStream, stats: &ForwardStats) {
+    let (target_host, target_port) = match read_socks_request(&mut stream) {
+        Ok(target) => target,
+        Err(e) => {
+            warn!("SOCKS handshake failed: {}", e);
+            return;
+        }
+    };
+
+    stats.total_connections.fetch_add(1, Ordering::Relaxed);
+    stats.active_connections.fetch_add(1, Ordering::Relaxed);
+
+    let opened = {
+        let conn = connection.lock().unwrap();
+        let session = conn.session();
+        session
+            .channel_direct_tcpip(&target_host, target_port, None)
+            .map(|channel| (session, channel))
+    };
+
+    match opened {
+        Ok((session, mut channel)) => {
+            if socks::send_reply(&mut stream, Reply::Succeeded).is_ok() {
+                if let Err(e) = tunnel::relay(&session, &mut channel, stream, connection, &stats.relay) {
+                    warn!("SOCKS connection to {}:{} failed: {}", target_host, target_port, e);
+                }
+                // Freeing the channel is a libssh2 call too
+                let _conn = connection.lock().unwrap();
+                drop(channel);
+            } else {
+                let _conn = connection.lock().unwrap();
+                channel.close().ok();
+                drop(channel);
+            }
+        }
+        Err(e) => {
+            warn!("direct-tcpip to {}:{} refused: {}", target_host, target_port, e);
+            // The server doesn't say why it refused; an unreachable target is the usual cause
+            socks::send_reply(&mut stream, Reply::HostUnreachable).ok();
+        }
+    }
+
+    stats.active_connections.fetch_sub(1, Ordering::Relaxed);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Read, Write};
+
+    /// Accepts one client on a listener from `bind_local`, handed over in
+    /// non-blocking mode as macOS, BSD and Windows do.
+    fn accept(listener: &TcpListener) -> TcpStream {
+        loop {
+            match listener.accept() {
+                Ok((stream, _)) => {
+                    stream.set_nonblocking(true).unwrap();
+                    return stream;
+                }
+                Err(e) if e.kind() == ErrorKind::WouldBlock => std::thread::sleep(Duration::from_millis(5)),
+                Err(e) => panic!("accept failed: {}", e),
+            }
+        }
+    }
+
+    #[test]
+    fn socks_request_read_from_non_blocking_socket() {
+        let (listener, port) = bind_local("127.0.0.1", 0).unwrap();
+        let client = std::thread::spawn(move || {
+            let mut client = TcpStream::connect(("127.0.0.1", port)).unwrap();
+            // Sent late, so the handshake has to wait for it
+            std::thread::sleep(Duration::from_millis(100));
+            client.write_all(&[5, 1, 0]).unwrap();
+            let mut choice = [0u8; 2];
+            client.read_exact(&mut choice).unwrap();
+            client.write_all(&[5, 1, 0, 1, 192, 0, 2, 7, 0, 22]).unwrap();
+            choice
+        });
+
+        let mut stream = accept(&listener);
+        let target = read_socks_request(&mut stream).unwrap();
+
+        assert_eq!(target, ("192.0.2.7".to_string(), 22));
+        assert_eq!(client.join().unwrap(), [5, 0]);
+    }
+}
@@ -0,0 +1,334 @@
+use libssh2_sys as raw;
+use ssh2::Session;
+use std::ffi::{c_void, CStr};
+use std::io::{self, ErrorKind, Read, Write};
+use std::os::raw::{c_char, c_int};
+use std::sync::{Mutex, Weak};
+use std::time::Duration;
+use log::{info, warn};
+
+use crate::channel::RawChannel;
+
+const RELAY_BUFFER_SIZE: usize = 32 * 1024;
+const IDLE_POLL_MIN: Duration = Duration::from_millis(2);
+const IDLE_POLL_MAX: Duration = Duration::from_millis(50);
+/// `libssh2_session_callback_set` type for incoming agent channels (`libssh2.h`).
+const LIBSSH2_CALLBACK_AUTHAGENT: c_int = 7;
+/// Same, for incoming X11 channels.
+const LIBSSH2_CALLBACK_X11: c_int = 4;
+
+extern "C" {
+    // In libssh2.h, but not declared by libssh2-sys
+    fn libssh2_session_callback_set(
+        session: *mut raw::LIBSSH2_SESSION,
+        cbtype: c_int,
+        callback: *mut c_void,
+    ) -> *mut c_void;
+}
+
+/// What the server opened a channel for.
+#[derive(Debug, Clone)]
+pub enum InboundKind {
+    /// A client on the server talking to our forwarded agent.
+    Agent,
+    /// An X client on the server, connecting from `origin` as the server
+    /// reports it.
+    X11 { origin: String },
+}
+
+/// A channel libssh2 accepted for us, not yet wrapped with its session.
+struct Queued(*mut raw::LIBSSH2_CHANNEL);
+
+// Only handed over to the thread serving its session
+unsafe impl Send for Queued {}
+
+type Accepted = Vec<(InboundKind, Queued)>;
+
+/// Channels accepted by the callbacks and not yet picked up, per session
+/// address. A session is only listed while it has an `Acceptor`.
+static ACCEPTED: Mutex<Vec<(usize, Accepted)>> = Mutex::new(Vec::new());
+
+fn queue(session: *mut raw::LIBSSH2_SESSION, kind: InboundKind, channel: *mut raw::LIBSSH2_CHANNEL) {
+    // Runs inside libssh2, so it must neither block on the session nor panic
+    let Ok(mut accepted) = ACCEPTED.lock() else {
+        return;
+    };
+    if let Some((_, channels)) = accepted.iter_mut().find(|(address, _)| *address == session as usize) {
+        channels.push((kind, Queued(channel)));
+    }
+}
+
+extern "C" fn on_agent_channel(
+    session: *mut raw::LIBSSH2_SESSION,
+    channel: *mut raw::LIBSSH2_CHANNEL,
+    _abstract: *mut *mut c_void,
+) {
+    queue(session, InboundKind::Agent, channel);
+}
+
+extern "C" fn on_x11_channel(
+    session: *mut raw::LIBSSH2_SESSION,
+    channel: *mut raw::LIBSSH2_CHANNEL,
+    shost: *const c_char,
+    sport: c_int,
+    _abstract: *mut *mut c_void,
+) {
+    let host = if shost.is_null() {
+        String::new()
+    } else {
+        unsafe { CStr::from_ptr(shost) }.to_string_lossy().into_owned()
+    };
+    let origin = format!("{}:{}", host, sport);
+    queue(session, InboundKind::X11 { origin }, channel);
+}
+
+/// Lets the server open channels of the kinds enabled on it, for as long as
+/// it lives. Channel opens libssh2 reads while nothing is accepting them are
+/// refused.
+pub struct Acceptor {
+    session: Session,
+    address: usize,
+}
+
+impl Acceptor {
+    pub fn new(session: &Session) -> Self {
+        let address = Self::raw_session(&mut session.raw()) as usize;
+        ACCEPTED.lock().unwrap().push((address, Vec::new()));
+        Self {
+            session: session.clone(),
+            address,
+        }
+    }
+
+    fn raw_session(session: &mut raw::LIBSSH2_SESSION) -> *mut raw::LIBSSH2_SESSION {
+        session
+    }
+
+    /// Accepts `auth-agent@openssh.com` channels, for agent forwarding.
+    pub fn accept_agent(&self) {
+        self.set_callback(LIBSSH2_CALLBACK_AUTHAGENT, on_agent_channel as *mut c_void);
+    }
+
+    /// Accepts `x11` channels, for X11 forwarding.
+    pub fn accept_x11(&self) {
+        self.set_callback(LIBSSH2_CALLBACK_X11, on_x11_channel as *mut c_void);
+    }
+
+    fn set_callback(&self, cbtype: c_int, callback: *mut c_void) {
+        let mut session = self.session.raw();
+        unsafe {
+            libssh2_session_callback_set(Self::raw_session(&mut session), cbtype, callback);
+        }
+    }
+
+    /// Takes the channels accepted since the last call.
+    fn take(&self) -> Vec<(InboundKind, RawChannel)> {
+        let queued = ACCEPTED
+            .lock()
+            .unwrap()
+            .iter_mut()
+            .find(|(address, _)| *address == self.address)
+            .map(|(_, channels)| std::mem::take(channels))
+            .unwrap_or_default();
+        queued
+            .into_iter()
+            .map(|(kind, Queued(channel))| (kind, unsafe { RawChannel::from_raw(&self.session, channel) }))
+            .collect()
+    }
+}
+
+impl Drop for Acceptor {
+    fn drop(&mut self) {
+        self.set_callback(LIBSSH2_CALLBACK_AUTHAGENT, std::ptr::null_mut());
+        self.set_callback(LIBSSH2_CALLBACK_X11, std::ptr::null_mut());
+        // Anything still queued is freed along with the session
+        ACCEPTED.lock().unwrap().retain(|(address, _)| *address != self.address);
+    }
+}
+
+/// The local end an inbound channel is relayed to. Both calls must not
+/// block; `read` returns `Ok(0)` once the peer has nothing more to send.
+pub trait Peer: Send {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize>;
+    fn write(&mut self, data: &[u8]) -> io::Result<usize>;
+    /// The server has sent EOF; nothing more will be written. May be called
+    /// more than once.
+    fn close_write(&mut self);
+}
+
+struct Relay {
+    kind: InboundKind,
+    channel: RawChannel,
+    peer: Box<dyn Peer>,
+    to_peer: Vec<u8>,
+    to_channel: Vec<u8>,
+    channel_eof: bool,
+    peer_eof: bool,
+    done: bool,
+}
+
+impl Relay {
+    fn new(kind: InboundKind, channel: RawChannel, peer: Box<dyn Peer>) -> Self {
+        Self {
+            kind,
+            channel,
+            peer,
+            to_peer: Vec::new(),
+            to_channel: Vec::new(),
+            channel_eof: false,
+            peer_eof: false,
+            done: false,
+        }
+    }
+
+    /// Moves data between the channel and the buffers. Called holding the
+    /// connection, in non-blocking mode.
+    fn pump_channel(&mut self, buffer: &mut [u8]) -> bool {
+        let mut progressed = false;
+
+        if self.to_peer.is_empty() && !self.channel_eof {
+            match self.channel.read(buffer) {
+                Ok(0) => {
+                    self.channel_eof = true;
+                    progressed = true;
+                }
+                Ok(n) => {
+                    self.to_peer.extend_from_slice(&buffer[..n]);
+                    progressed = true;
+                }
+                Err(e) if e.kind() == ErrorKind::WouldBlock => {}
+                Err(e) => return self.fail(e),
+            }
+        }
+        if !self.to_channel.is_empty() {
+            match self.channel.write(&self.to_channel) {
+                Ok(n) => {
+                    self.to_channel.drain(..n);
+                    progressed = true;
+                }
+                Err(e) if e.kind() == ErrorKind::WouldBlock => {}
+                Err(e) => return self.fail(e),
+            }
+        }
+
+        // Whatever the server still sends has nowhere to go
+        if self.peer_eof && self.to_channel.is_empty() {
+            self.done = true;
+        }
+        progressed
+    }
+
+    /// Moves data between the buffers and the peer, without the session.
+    fn pump_peer(&mut self, buffer: &mut [u8]) -> bool {
+        let mut progressed = false;
+
+        if !self.to_peer.is_empty() {
+            match self.peer.write(&self.to_peer) {
+                Ok(n) => {
+                    self.to_peer.drain(..n);
+                    progressed = true;
+                }
+                Err(e) if e.kind() == ErrorKind::WouldBlock => {}
+                Err(e) => return self.fail(e),
+            }
+        }
+        if self.channel_eof && self.to_peer.is_empty() {
+            self.peer.close_write();
+        }
+
+        if self.to_channel.is_empty() && !self.peer_eof {
+            match self.peer.read(buffer) {
+                Ok(0) => {
+                    self.peer_eof = true;
+                    progressed = true;
+                }
+                Ok(n) => {
+                    self.to_channel.extend_from_slice(&buffer[..n]);
+                    progressed = true;
+                }
+                Err(e) if e.kind() == ErrorKind::WouldBlock => {}
+                Err(e) => return self.fail(e),
+            }
+        }
+        progressed
+    }
+
+    fn fail(&mut self, e: io::Error) -> bool {
+        warn!("Inbound {:?} channel failed: {}", self.kind, e);
+        self.done = true;
+        true
+    }
+}
+
+/// Relays every channel the server opens through `acceptor` to a peer from
+/// `open_peer`, until `connection` is dropped. A channel `open_peer` fails
+/// for is closed straight away.
+///
+/// Like `tunnel::relay`, every libssh2 call is made in non-blocking mode
+/// while holding the connection's lock; peers are served without it.
+pub fn spawn_service<T, F>(label: &str, acceptor: Acceptor, connection: Weak<Mutex<T>>, mut open_peer: F)
+where
+    T: Send + 'static,
+    F: FnMut(&InboundKind) -> io::Result<Box<dyn Peer>> + Send + 'static,
+{
+    let thread_name = format!("ssh-inbound-{}", &label[..8]);
+    let result = std::thread::Builder::new().name(thread_name).spawn(move || {
+        let session = acceptor.session.clone();
+        let mut relays: Vec<Relay> = Vec::new();
+        // Channels being closed, which can take a round-trip
+        let mut closing: Vec<RawChannel> = Vec::new();
+        let mut buffer = vec![0u8; RELAY_BUFFER_SIZE];
+        let mut idle_poll = IDLE_POLL_MIN;
+
+        loop {
+            let mut progressed = false;
+            for (kind, channel) in acceptor.take() {
+                info!("Server opened {:?} channel", kind);
+                match open_peer(&kind) {
+                    Ok(peer) => relays.push(Relay::new(kind, channel, peer)),
+                    Err(e) => {
+                        warn!("No local end for {:?} channel: {}", kind, e);
+                        closing.push(channel);
+                    }
+                }
+                progressed = true;
+            }
+
+            {
+                let Some(conn) = connection.upgrade() else {
+                    break;
+                };
+                let _guard = conn.lock().unwrap();
+                session.set_blocking(false);
+                for relay in &mut relays {
+                    progressed |= relay.pump_channel(&mut buffer);
+                }
+                let (done, active) = relays.drain(..).partition(|relay| relay.done);
+                relays = active;
+                closing.extend(done.into_iter().map(|relay: Relay| relay.channel));
+                closing.retain_mut(|channel| !channel.try_free());
+                session.set_blocking(true);
+            }
+
+            for relay in &mut relays {
+                progressed |= relay.pump_peer(&mut buffer);
+            }
+
+            if progressed {
+                idle_poll = IDLE_POLL_MIN;
+            } else {
+                std::thread::sleep(idle_poll);
+                idle_poll = (idle_poll * 2).min(IDLE_POLL_MAX);
+            }
+        }
+        // Nothing else uses the session now; don't wait on the server to
+        // close what is still open
+        session.set_blocking(false);
+        drop(relays);
+        drop(closing);
+    });
+
+    if let Err(e) = result {
+        warn!("Failed to spawn inbound channel thread: {}", e);
+    }
+}
@@ -0,0 +1,62 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Returns true if the private key needs a passphrase before it can be used.
+///
+/// Handles both OpenSSH-format keys and legacy PEM keys (`Proc-Type: 4,ENCRYPTED`
+/// or PKCS#8 `ENCRYPTED PRIVATE KEY`).
+pub fn is_encrypted(pem: &str) -> bool {
+    match ssh_key::PrivateKey::from_openssh(pem) {
+        Ok(key) => key.is_encrypted(),
+        Err(_) => pem.contains("ENCRYPTED"),
+    }
+}
+
+/// Checks a passphrase locally where the key format allows it.
+///
+/// Returns `None` for formats we can't decrypt ourselves (legacy PEM); the
+/// server round-trip is then the only way to find out.
+pub fn check_passphrase(pem: &str, passphrase: &str) -> Option<bool> {
+    let key = ssh_key::PrivateKey::from_openssh(pem).ok()?;
+    Some(key.decrypt(passphrase).is_ok())
+}
+
+/// Name a stored key's passphrase is cached under.
+pub fn stored_key_label(name: &str) -> String {
+    format!("{} (stored)", name)
+}
+
+/// Remembers key passphrases in memory for a limited time.
+pub struct PassphraseCache {
+    entries: Mutex<HashMap<String, (String, Instant)>>,
+}
+
+impl PassphraseCache {
+    pub fn new() -> Self {
+        Self {
+            entries: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn get(&self, key_id: &str) -> Option<String> {
+        let mut entries = self.entries.lock().unwrap();
+        entries.retain(|_, (_, expires)| *expires > Instant::now());
+        entries.get(key_id).map(|(passphrase, _)| passphrase.clone())
+    }
+
+    pub fn insert(&self, key_id: &str, passphrase: &str, ttl: Duration) {
+        self.entries
+            .lock()
+            .unwrap()
+            .insert(key_id.to_string(), (passphrase.to_string(), Instant::now() + ttl));
+    }
+
+    pub fn remove(&self, key_id: &str) {
+        self.entries.lock().unwrap().remove(key_id);
+    }
+
+    pub fn clear(&self) {
+        self.entries.lock().unwrap().clear();
+    }
+}
@@ -0,0 +1,234 @@
+use anyhow::Result;
+use serde::Serialize;
+use ssh2::{CheckResult, HashType, HostKeyType, KnownHostFileKind, KnownHosts, Session};
+use std::path::PathBuf;
+use log::{info, warn};
+
+use crate::crypto::base64;
+use crate::session::SessionManager;
+use crate::ssh::SshError;
+
+/// Description of a server's host key, as shown to the user.
+#[derive(Debug, Clone, Serialize)]
+pub struct HostKeyInfo {
+    pub host: String,
+    pub port: u16,
+    pub key_type: String,
+    pub fingerprint: String,
+}
+
+pub enum HostKeyStatus {
+    Trusted,
+    Unknown,
+    Changed,
+}
+
+/// Known host keys in OpenSSH `known_hosts` format.
+///
+/// Keys accepted in the app are written to `known_hosts` in the app config
+/// directory. The user's `~/.ssh/known_hosts` is also consulted, read-only.
+pub struct KnownHostsStore {
+    path: PathBuf,
+}
+
+impl KnownHostsStore {
+    pub fn new() -> Self {
+        Self {
+            path: SessionManager::get_config_dir().join("known_hosts"),
+        }
+    }
+
+    /// Host pattern used in `known_hosts` lines, `[host]:port` for non-default ports.
+    fn host_pattern(host: &str, port: u16) -> String {
+        if port == 22 {
+            host.to_string()
+        } else {
+            format!("[{}]:{}", host, port)
+        }
+    }
+
+    fn user_known_hosts() -> Option<PathBuf> {
+        dirs::home_dir().map(|home| home.join(".ssh").join("known_hosts"))
+    }
+
+    fn load_own(&self, known: &mut KnownHosts) -> Result<(), SshError> {
+        if self.path.exists() {
+            known.read_file(&self.path, KnownHostFileKind::OpenSSH)
+                .map_err(|e| SshError::HostKeyVerificationFailed(format!("Failed to read {}: {}", self.path.display(), e)))?;
+        }
+        Ok(())
+    }
+
+    pub fn host_key_info(session: &Session, host: &str, port: u16) -> Result<HostKeyInfo, SshError> {
+        let (_, key_type) = session.host_key()
+            .ok_or_else(|| SshError::HostKeyVerificationFailed("Server sent no host key".to_string()))?;
+        let hash = session.host_key_hash(HashType::Sha256)
+            .ok_or_else(|| SshError::HostKeyVerificationFailed("Failed to hash host key".to_string()))?;
+
+        Ok(HostKeyInfo {
+            host: host.to_string(),
+            port,
+            key_type: key_type_name(key_type).to_string(),
+            fingerprint: format!("SHA256:{}", base64::encode(hash).trim_end_matches('=')),
+        })
+    }
+
+    /// Checks the session's host key against the app store and `~/.ssh/known_hosts`.
+    /// Only keys of the offered type count, so a host with just other types
+    /// on record is `Unknown` rather than `Changed`.
+    pub fn check(&self, session: &Session, host: &str, port: u16) -> Result<HostKeyStatus, SshError> {
+        let (key, _) = session.host_key()
+            .ok_or_else(|| SshError::HostKeyVerificationFailed("Server sent no host key".to_string()))?;
+
+        let mut known = session.known_hosts()
+            .map_err(|e| SshError::HostKeyVerificationFailed(e.to_string()))?;
+        self.load_own(&mut known)?;
+
+        if let Some(user_file) = Self::user_known_hosts().filter(|p| p.exists()) {
+            // Entries libssh2 can't parse (e.g. security-key types) abort the read;
+            // whatever was loaded before that point is still used.
+            if let Err(e) = known.read_file(&user_file, KnownHostFileKind::OpenSSH) {
+                warn!("Could not fully read {}: {}", user_file.display(), e);
+            }
+        }
+
+        // libssh2 compares against stored keys of every type, which would
+        // make a host offering a new key type look like an impostor
+        let offered_type = key_type_of(key);
+        let mut same_type = session.known_hosts()
+            .map_err(|e| SshError::HostKeyVerificationFailed(e.to_string()))?;
+        let entries = known.hosts()
+            .map_err(|e| SshError::HostKeyVerificationFailed(e.to_string()))?;
+        for entry in entries {
+            let stored = base64::decode(entry.key()).ok();
+            if stored.as_deref().and_then(key_type_of) != offered_type {
+                continue;
+            }
+            if let Ok(line) = known.write_string(&entry, KnownHostFileKind::OpenSSH) {
+                same_type.read_str(line.trim_end(), KnownHostFileKind::OpenSSH).ok();
+            }
+        }
+
+        match same_type.check_port(host, port, key) {
+            CheckResult::Match => Ok(HostKeyStatus::Trusted),
+            CheckResult::NotFound => Ok(HostKeyStatus::Unknown),
+            CheckResult::Mismatch => Ok(HostKeyStatus::Changed),
+            CheckResult::Failure => Err(SshError::HostKeyVerificationFailed(
+                "known_hosts lookup failed".to_string(),
+            )),
+        }
+    }
+
+    /// Records the session's host key for `host:port` in the app store.
+    pub fn trust(&self, session: &Session, host: &str, port: u16) -> Result<(), SshError> {
+        let (key, key_type) = session.host_key()
+            .ok_or_else(|| SshError::HostKeyVerificationFailed("Server sent no host key".to_string()))?;
+
+        let mut known = session.known_hosts()
+            .map_err(|e| SshError::HostKeyVerificationFailed(e.to_string()))?;
+        self.load_own(&mut known)?;
+
+        known.add(&Self::host_pattern(host, port), key, "", key_type.into())
+            .map_err(|e| SshError::HostKeyVerificationFailed(format!("Failed to add host key: {}", e)))?;
+
+        if let Some(parent) = self.path.parent() {
+            std::fs::create_dir_all(parent).ok();
+        }
+        known.write_file(&self.path, KnownHostFileKind::OpenSSH)
+            .map_err(|e| SshError::HostKeyVerificationFailed(format!("Failed to write {}: {}", self.path.display(), e)))?;
+
+        info!("Added host key for {}:{} to {}", host, port, self.path.display());
+        Ok(())
+    }
+
+    /// Lists the entries in the app store.
+    pub fn list(&self) -> Result<Vec<HostKeyInfo>> {
+        if !self.path.exists() {
+            return Ok(Vec::new());
+        }
+
+        let content = std::fs::read_to_string(&self.path)?;
+        let mut entries = Vec::new();
+        for line in content.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let mut fields = line.split_whitespace();
+            let (Some(pattern), Some(key_type), Some(key)) = (fields.next(), fields.next(), fields.next()) else {
+                continue;
+            };
+            let (host, port) = parse_host_pattern(pattern);
+            let fingerprint = ssh_key::PublicKey::from_openssh(&format!("{} {}", key_type, key))
+                .map(|k| k.fingerprint(ssh_key::HashAlg::Sha256).to_string())
+                .unwrap_or_default();
+
+            entries.push(HostKeyInfo {
+                host,
+                port,
+                key_type: key_type.to_string(),
+                fingerprint,
+            });
+        }
+
+        Ok(entries)
+    }
+
+    /// Removes every key recorded for `host:port` from the app store.
+    pub fn remove(&self, host: &str, port: u16) -> Result<()> {
+        if !self.path.exists() {
+            return Ok(());
+        }
+
+        let pattern = Self::host_pattern(host, port);
+        let content = std::fs::read_to_string(&self.path)?;
+        let kept: Vec<&str> = content
+            .lines()
+            .filter(|line| {
+                line.split_whitespace()
+                    .next()
+                    .map(|hosts| !hosts.split(',').any(|h| h == pattern))
+                    .unwrap_or(true)
+            })
+            .collect();
+
+        let mut output = kept.join("\n");
+        if !output.is_empty() {
+            output.push('\n');
+        }
+        std::fs::write(&self.path, output)?;
+
+        Ok(())
+    }
+}
+
+fn parse_host_pattern(pattern: &str) -> (String, u16) {
+    let first = pattern.split(',').next().unwrap_or(pattern);
+    if let Some(rest) = first.strip_prefix('[') {
+        if let Some((host, port)) = rest.split_once("]:") {
+            if let Ok(port) = port.parse() {
+                return (host.to_string(), port);
+            }
+        }
+    }
+    (first.to_string(), 22)
+}
+
+/// The algorithm name a public key blob starts with, e.g. `ssh-ed25519`.
+fn key_type_of(blob: &[u8]) -> Option<&[u8]> {
+    let len = u32::from_be_bytes(blob.get(..4)?.try_into().ok()?) as usize;
+    blob.get(4..4 + len)
+}
+
+fn key_type_name(key_type: HostKeyType) -> &'static str {
+    match key_type {
+        HostKeyType::Rsa => "ssh-rsa",
+        HostKeyType::Dss => "ssh-dss",
+        HostKeyType::Ecdsa256 => "ecdsa-sha2-nistp256",
+        HostKeyType::Ecdsa384 => "ecdsa-sha2-nistp384",
+        HostKeyType::Ecdsa521 => "ecdsa-sha2-nistp521",
+        HostKeyType::Ed25519 => "ssh-ed25519",
+        HostKeyType::Unknown => "unknown",
+    }
+}
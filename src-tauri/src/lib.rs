@@ -4,59 +4,171 @@
 )]
 
 mod ssh;
+mod agent;
+mod algorithms;
+mod channel;
+mod charset;
 mod crypto;
 mod session;
 mod config;
+mod exec;
+mod fanout;
+mod forward;
+mod inbound;
+mod keys;
+mod known_hosts;
+mod prompt;
+mod proxy;
+mod sftp;
+mod socks;
+mod transfer;
+mod tunnel;
+mod x11;
 
 use std::sync::Arc;
+use std::time::Duration;
 use tauri::{Manager, State};
 use tokio::sync::Mutex;
 use ssh_key::{Algorithm, PrivateKey, LineEnding};
 use rand::rngs::OsRng;
 
-use crate::ssh::{SshManager, SshConnection};
-use crate::session::{SessionManager, ConnectionConfig};
+use crate::algorithms::{AlgorithmPreferences, NegotiatedMethods};
+use crate::ssh::{ConnectParams, SshManager, SshConnection};
+use crate::session::{SessionManager, ConnectionConfig, JumpHost, ProxySetting};
 use crate::crypto::SecureStorage;
+use crate::config::{
+    session_proxy_password_key, AppConfig, ConfigManager, KeepaliveConfig, ProxyConfig, ReconnectConfig,
+    SecurityConfig, TimeoutConfig, TransferConfig, PROXY_PASSWORD_KEY,
+};
+use crate::exec::ExecResult;
+use crate::fanout::{FanoutManager, FanoutRun, FanoutTarget};
+use crate::forward::ForwardInfo;
+use crate::known_hosts::HostKeyInfo;
+use crate::prompt::PromptBroker;
+use crate::sftp::SftpEntry;
+use crate::transfer::{TransferDirection, TransferInfo};
+
+/// Timeout for `exec_command` when the caller doesn't give one.
+const DEFAULT_EXEC_TIMEOUT_SECS: u64 = 30;
+/// Hosts a fan-out run talks to at once when the caller doesn't say.
+const DEFAULT_FANOUT_CONCURRENCY: usize = 10;
 
 pub struct AppState {
-    ssh_manager: Arc<Mutex<SshManager>>,
+    ssh_manager: Arc<SshManager>,
     session_manager: Arc<Mutex<SessionManager>>,
     secure_storage: Arc<Mutex<SecureStorage>>,
+    config_manager: Arc<Mutex<ConfigManager>>,
+    prompts: Arc<PromptBroker>,
+    fanout: Arc<FanoutManager>,
 }
 
 #[tauri::command]
 async fn connect_ssh(
     state: State<'_, AppState>,
-    host: String,
-    port: u16,
-    username: String,
-    auth_type: String,
-    auth_value: String,
+    config: ConnectionConfig,
+    pending_id: Option<String>,
 ) -> Result<String, String> {
-    let mut manager = state.ssh_manager.lock().await;
+    let app_config = state.config_manager.lock().await.get_config().clone();
     
     let connection = ConnectionConfig {
-        host,
-        port,
-        username,
-        auth_type,
-        auth_value,
+        group: None,
+        ..config
     };
+    let hops = resolve_jump_hosts(&state, &connection.jump_hosts).await?;
+    let proxy = resolve_proxy(&state, &connection.proxy, &app_config).await?;
     
-    match manager.connect(connection).await {
+    let params = ConnectParams {
+        proxy,
+        config: connection,
+        jump_hosts: hops,
+        security: app_config.security,
+        keepalive: app_config.keepalive,
+        reconnect: app_config.reconnect,
+        timeouts: app_config.timeouts,
+    };
+    
+    let pending_id = pending_id.unwrap_or_else(|| uuid::Uuid::new_v4().to_string());
+    match state.ssh_manager.connect(params, &pending_id).await {
         Ok(session_id) => Ok(session_id),
         Err(e) => Err(format!("Connection failed: {}", e)),
     }
 }
 
+#[tauri::command]
+fn cancel_connect(
+    state: State<'_, AppState>,
+    pending_id: String,
+) -> Result<(), String> {
+    state.ssh_manager.cancel_connect(&pending_id)
+        .map_err(|e| e.to_string())
+}
+
+/// Looks up each named jump host among the saved sessions, with its credentials decrypted.
+async fn resolve_jump_hosts(
+    state: &AppState,
+    names: &[String],
+) -> Result<Vec<JumpHost>, String> {
+    resolve_saved_sessions(state, names, "Jump host").await
+}
+
+/// Looks up saved sessions by name, with their credentials decrypted.
+/// `role` names what they are needed as, for error messages.
+async fn resolve_saved_sessions(
+    state: &AppState,
+    names: &[String],
+    role: &str,
+) -> Result<Vec<JumpHost>, String> {
+    let session_manager = state.session_manager.lock().await;
+    let secure_storage = state.secure_storage.lock().await;
+    
+    let mut sessions = Vec::new();
+    for name in names {
+        let (mut config, encrypted_auth) = session_manager.get_session(name).await
+            .map_err(|e| format!("{} '{}': {}", role, name, e))?;
+        config.auth_value = secure_storage.decrypt(&encrypted_auth)
+            .map_err(|e| format!("{} '{}': decryption failed: {}", role, name, e))?;
+        sessions.push(JumpHost {
+            name: name.clone(),
+            config,
+        });
+    }
+    
+    Ok(sessions)
+}
+
+/// The proxy `setting` comes to given the app's, with its password read back
+/// from secure storage.
+async fn resolve_proxy(
+    state: &AppState,
+    setting: &ProxySetting,
+    app_config: &AppConfig,
+) -> Result<Option<ProxyConfig>, String> {
+    let Some(mut proxy) = setting.resolve(app_config.proxy.as_ref()) else {
+        return Ok(None);
+    };
+    let secure_storage = state.secure_storage.lock().await;
+    proxy.load_password(&secure_storage)
+        .map_err(|e| format!("Proxy password unavailable: {}", e))?;
+    Ok(Some(proxy))
+}
+
+/// Opens another shell on an existing session's connection, reusing its
+/// login. Returns the new shell's session ID.
+#[tauri::command]
+async fn open_shell_channel(
+    state: State<'_, AppState>,
+    session_id: String,
+) -> Result<String, String> {
+    state.ssh_manager.open_shell(&session_id).await
+        .map_err(|e| format!("Failed to open shell: {}", e))
+}
+
 #[tauri::command]
 async fn disconnect_ssh(
     state: State<'_, AppState>,
     session_id: String,
 ) -> Result<(), String> {
-    let mut manager = state.ssh_manager.lock().await;
-    
-    match manager.disconnect(&session_id).await {
+    match state.ssh_manager.disconnect(&session_id).await {
         Ok(_) => Ok(()),
         Err(e) => Err(format!("Disconnect failed: {}", e)),
     }
@@ -68,49 +180,485 @@ async fn send_command(
     session_id: String,
     command: String,
 ) -> Result<(), String> {
-    let manager = state.ssh_manager.lock().await;
-    
-    match manager.send_command(&session_id, &command).await {
+    match state.ssh_manager.send_command(&session_id, &command).await {
         Ok(()) => Ok(()),
         Err(e) => Err(format!("Command failed: {}", e)),
     }
 }
 
 #[tauri::command]
-async fn read_output(
+fn get_broadcast_group(state: State<'_, AppState>) -> Vec<String> {
+    state.ssh_manager.broadcast_group()
+}
+
+#[tauri::command]
+fn set_broadcast_group(
+    state: State<'_, AppState>,
+    session_ids: Vec<String>,
+) -> Result<(), String> {
+    state.ssh_manager.set_broadcast_group(session_ids)
+        .map_err(|e| format!("Broadcast failed: {}", e))
+}
+
+#[tauri::command]
+fn add_to_broadcast(
+    state: State<'_, AppState>,
+    session_id: String,
+) -> Result<(), String> {
+    state.ssh_manager.add_to_broadcast(&session_id)
+        .map_err(|e| format!("Broadcast failed: {}", e))
+}
+
+#[tauri::command]
+fn remove_from_broadcast(
+    state: State<'_, AppState>,
+    session_id: String,
+) {
+    state.ssh_manager.remove_from_broadcast(&session_id);
+}
+
+#[tauri::command]
+async fn exec_command(
     state: State<'_, AppState>,
     session_id: String,
+    command: String,
+    timeout_secs: Option<u64>,
+) -> Result<ExecResult, String> {
+    let timeout = Duration::from_secs(timeout_secs.unwrap_or(DEFAULT_EXEC_TIMEOUT_SECS));
+    
+    state.ssh_manager.exec(&session_id, &command, timeout).await
+        .map_err(|e| format!("Exec failed: {}", e))
+}
+
+#[tauri::command]
+async fn run_fanout(
+    state: State<'_, AppState>,
+    session_names: Option<Vec<String>>,
+    group: Option<String>,
+    command: String,
+    concurrency: Option<usize>,
+    timeout_secs: Option<u64>,
 ) -> Result<String, String> {
-    let manager = state.ssh_manager.lock().await;
+    let mut names = session_names.unwrap_or_default();
+    if let Some(group) = group {
+        let members = state.session_manager.lock().await.sessions_in_group(&group).await
+            .map_err(|e| format!("Load failed: {}", e))?;
+        names.extend(members);
+    }
+    names.sort();
+    names.dedup();
+    if names.is_empty() {
+        return Err("No sessions selected".to_string());
+    }
     
-    match manager.read_output(&session_id).await {
-        Ok(output) => Ok(output),
-        Err(e) => Err(format!("Read failed: {}", e)),
+    let app_config = state.config_manager.lock().await.get_config().clone();
+    
+    let mut targets = Vec::new();
+    for session in resolve_saved_sessions(&state, &names, "Session").await? {
+        let jump_hosts = resolve_jump_hosts(&state, &session.config.jump_hosts).await?;
+        let proxy = resolve_proxy(&state, &session.config.proxy, &app_config).await?;
+        targets.push(FanoutTarget {
+            name: session.name,
+            params: ConnectParams {
+                proxy,
+                config: session.config,
+                jump_hosts,
+                security: app_config.security.clone(),
+                keepalive: app_config.keepalive.clone(),
+                reconnect: app_config.reconnect.clone(),
+                timeouts: app_config.timeouts.clone(),
+            },
+        });
     }
+    
+    let timeout = Duration::from_secs(timeout_secs.unwrap_or(DEFAULT_EXEC_TIMEOUT_SECS));
+    
+    Ok(state.fanout.start(
+        state.ssh_manager.clone(),
+        targets,
+        command,
+        concurrency.unwrap_or(DEFAULT_FANOUT_CONCURRENCY),
+        timeout,
+    ))
 }
 
 #[tauri::command]
-async fn save_session(
+fn get_fanout_run(
+    state: State<'_, AppState>,
+    run_id: String,
+) -> Result<FanoutRun, String> {
+    state.fanout.get(&run_id).ok_or_else(|| "Run not found".to_string())
+}
+
+#[tauri::command]
+fn export_fanout_run(
+    state: State<'_, AppState>,
+    run_id: String,
+) -> Result<String, String> {
+    state.fanout.export_json(&run_id).ok_or_else(|| "Run not found".to_string())
+}
+
+#[tauri::command]
+fn remove_fanout_run(
+    state: State<'_, AppState>,
+    run_id: String,
+) {
+    state.fanout.remove(&run_id);
+}
+
+#[tauri::command]
+async fn start_local_forward(
+    state: State<'_, AppState>,
+    session_id: String,
+    bind_host: Option<String>,
+    bind_port: u16,
+    remote_host: String,
+    remote_port: u16,
+) -> Result<ForwardInfo, String> {
+    let bind_host = bind_host.unwrap_or_else(|| "127.0.0.1".to_string());
+    
+    state.ssh_manager
+        .start_local_forward(&session_id, &bind_host, bind_port, &remote_host, remote_port)
+        .map_err(|e| format!("Forward failed: {}", e))
+}
+
+#[tauri::command]
+async fn start_remote_forward(
+    state: State<'_, AppState>,
+    session_id: String,
+    remote_bind_host: Option<String>,
+    remote_port: u16,
+    local_host: Option<String>,
+    local_port: u16,
+) -> Result<ForwardInfo, String> {
+    let local_host = local_host.unwrap_or_else(|| "127.0.0.1".to_string());
+    let ssh_manager = state.ssh_manager.clone();
+    
+    // Registering the listener is a round-trip on the session; keep it off the async runtime
+    tokio::task::spawn_blocking(move || {
+        ssh_manager.start_remote_forward(
+            &session_id,
+            remote_bind_host.as_deref(),
+            remote_port,
+            &local_host,
+            local_port,
+        )
+    })
+    .await
+    .map_err(|e| format!("Forward failed: {}", e))?
+    .map_err(|e| format!("Forward failed: {}", e))
+}
+
+#[tauri::command]
+async fn start_dynamic_forward(
+    state: State<'_, AppState>,
+    session_id: String,
+    bind_host: Option<String>,
+    bind_port: u16,
+) -> Result<ForwardInfo, String> {
+    let bind_host = bind_host.unwrap_or_else(|| "127.0.0.1".to_string());
+    
+    state.ssh_manager
+        .start_dynamic_forward(&session_id, &bind_host, bind_port)
+        .map_err(|e| format!("Forward failed: {}", e))
+}
+
+#[tauri::command]
+fn list_forwards(
+    state: State<'_, AppState>,
+    session_id: Option<String>,
+) -> Vec<ForwardInfo> {
+    state.ssh_manager.list_forwards(session_id.as_deref())
+}
+
+#[tauri::command]
+fn stop_forward(
+    state: State<'_, AppState>,
+    forward_id: String,
+) -> Result<(), String> {
+    state.ssh_manager.stop_forward(&forward_id)
+        .map_err(|e| format!("Stop forward failed: {}", e))
+}
+
+#[tauri::command]
+async fn sftp_list(
+    state: State<'_, AppState>,
+    session_id: String,
+    path: String,
+) -> Result<Vec<SftpEntry>, String> {
+    state.ssh_manager.sftp_list(&session_id, &path).await
+        .map_err(|e| format!("List failed: {}", e))
+}
+
+#[tauri::command]
+async fn sftp_stat(
+    state: State<'_, AppState>,
+    session_id: String,
+    path: String,
+) -> Result<SftpEntry, String> {
+    state.ssh_manager.sftp_stat(&session_id, &path).await
+        .map_err(|e| format!("Stat failed: {}", e))
+}
+
+#[tauri::command]
+async fn sftp_mkdir(
+    state: State<'_, AppState>,
+    session_id: String,
+    path: String,
+    mode: Option<u32>,
+) -> Result<(), String> {
+    let mode = mode.unwrap_or(sftp::DEFAULT_DIR_MODE);
+    state.ssh_manager.sftp_mkdir(&session_id, &path, mode).await
+        .map_err(|e| format!("Create directory failed: {}", e))
+}
+
+#[tauri::command]
+async fn sftp_rename(
+    state: State<'_, AppState>,
+    session_id: String,
+    from: String,
+    to: String,
+) -> Result<(), String> {
+    state.ssh_manager.sftp_rename(&session_id, &from, &to).await
+        .map_err(|e| format!("Rename failed: {}", e))
+}
+
+#[tauri::command]
+async fn sftp_delete(
+    state: State<'_, AppState>,
+    session_id: String,
+    path: String,
+) -> Result<(), String> {
+    state.ssh_manager.sftp_delete(&session_id, &path).await
+        .map_err(|e| format!("Delete failed: {}", e))
+}
+
+#[tauri::command]
+async fn sftp_chmod(
+    state: State<'_, AppState>,
+    session_id: String,
+    path: String,
+    mode: u32,
+) -> Result<(), String> {
+    state.ssh_manager.sftp_chmod(&session_id, &path, mode).await
+        .map_err(|e| format!("Change permissions failed: {}", e))
+}
+
+#[tauri::command]
+fn sftp_upload(
+    state: State<'_, AppState>,
+    session_id: String,
+    local_path: String,
+    remote_path: String,
+    resume: Option<bool>,
+) -> Result<TransferInfo, String> {
+    state.ssh_manager
+        .queue_transfer(&session_id, TransferDirection::Upload, &local_path, &remote_path, resume.unwrap_or(false))
+        .map_err(|e| format!("Upload failed: {}", e))
+}
+
+#[tauri::command]
+fn sftp_download(
+    state: State<'_, AppState>,
+    session_id: String,
+    remote_path: String,
+    local_path: String,
+    resume: Option<bool>,
+) -> Result<TransferInfo, String> {
+    state.ssh_manager
+        .queue_transfer(&session_id, TransferDirection::Download, &local_path, &remote_path, resume.unwrap_or(false))
+        .map_err(|e| format!("Download failed: {}", e))
+}
+
+#[tauri::command]
+fn list_transfers(
+    state: State<'_, AppState>,
+    session_id: Option<String>,
+) -> Vec<TransferInfo> {
+    state.ssh_manager.transfers().list(session_id.as_deref())
+}
+
+#[tauri::command]
+fn cancel_transfer(
+    state: State<'_, AppState>,
+    transfer_id: String,
+) -> Result<(), String> {
+    state.ssh_manager.transfers().cancel(&transfer_id)
+        .map_err(|e| format!("Cancel failed: {}", e))
+}
+
+#[tauri::command]
+fn retry_transfer(
+    state: State<'_, AppState>,
+    transfer_id: String,
+    session_id: Option<String>,
+) -> Result<TransferInfo, String> {
+    state.ssh_manager.retry_transfer(&transfer_id, session_id.as_deref())
+        .map_err(|e| format!("Retry failed: {}", e))
+}
+
+#[tauri::command]
+fn clear_finished_transfers(state: State<'_, AppState>) {
+    state.ssh_manager.transfers().clear_finished();
+}
+
+#[tauri::command]
+fn respond_prompt(
+    state: State<'_, AppState>,
+    id: String,
+    response: serde_json::Value,
+) -> Result<(), String> {
+    if state.prompts.respond(&id, response) {
+        Ok(())
+    } else {
+        Err("Prompt is no longer pending".to_string())
+    }
+}
+
+#[tauri::command]
+async fn list_known_hosts(
+    state: State<'_, AppState>,
+) -> Result<Vec<HostKeyInfo>, String> {
+    state.ssh_manager.known_hosts().list()
+        .map_err(|e| format!("Failed to read known hosts: {}", e))
+}
+
+#[tauri::command]
+async fn remove_known_host(
     state: State<'_, AppState>,
-    name: String,
     host: String,
     port: u16,
-    username: String,
-    auth_type: String,
-    auth_value: String,
+) -> Result<(), String> {
+    state.ssh_manager.known_hosts().remove(&host, port)
+        .map_err(|e| format!("Failed to remove known host: {}", e))
+}
+
+#[tauri::command]
+fn clear_passphrase_cache(
+    state: State<'_, AppState>,
+) {
+    state.ssh_manager.clear_passphrase_cache();
+}
+
+#[tauri::command]
+async fn get_app_config(
+    state: State<'_, AppState>,
+) -> Result<AppConfig, String> {
+    let config_manager = state.config_manager.lock().await;
+    Ok(config_manager.get_config().clone())
+}
+
+#[tauri::command]
+async fn update_security_config(
+    state: State<'_, AppState>,
+    security: SecurityConfig,
+) -> Result<(), String> {
+    let mut config_manager = state.config_manager.lock().await;
+    
+    // Cached passphrases must not outlive the setting that allowed them
+    if !security.ssh_key_passphrase_cache {
+        state.ssh_manager.clear_passphrase_cache();
+    }
+    
+    config_manager.update_config(|config| config.security = security)
+        .map_err(|e| format!("Failed to save config: {}", e))
+}
+
+#[tauri::command]
+async fn update_transfer_config(
+    state: State<'_, AppState>,
+    transfers: TransferConfig,
+) -> Result<(), String> {
+    let mut config_manager = state.config_manager.lock().await;
+    
+    state.ssh_manager.transfers().set_max_concurrent(transfers.max_concurrent);
+    
+    config_manager.update_config(|config| config.transfers = transfers)
+        .map_err(|e| format!("Failed to save config: {}", e))
+}
+
+#[tauri::command]
+async fn update_keepalive_config(
+    state: State<'_, AppState>,
+    keepalive: KeepaliveConfig,
+) -> Result<(), String> {
+    let mut config_manager = state.config_manager.lock().await;
+    
+    // Applies to connections opened from now on
+    config_manager.update_config(|config| config.keepalive = keepalive)
+        .map_err(|e| format!("Failed to save config: {}", e))
+}
+
+#[tauri::command]
+async fn update_reconnect_config(
+    state: State<'_, AppState>,
+    reconnect: ReconnectConfig,
+) -> Result<(), String> {
+    let mut config_manager = state.config_manager.lock().await;
+    
+    // Applies to connections opened from now on
+    config_manager.update_config(|config| config.reconnect = reconnect)
+        .map_err(|e| format!("Failed to save config: {}", e))
+}
+
+#[tauri::command]
+async fn update_timeout_config(
+    state: State<'_, AppState>,
+    timeouts: TimeoutConfig,
+) -> Result<(), String> {
+    let mut config_manager = state.config_manager.lock().await;
+    
+    // Applies to connections opened from now on
+    config_manager.update_config(|config| config.timeouts = timeouts)
+        .map_err(|e| format!("Failed to save config: {}", e))
+}
+
+#[tauri::command]
+async fn update_proxy_config(
+    state: State<'_, AppState>,
+    mut proxy: Option<ProxyConfig>,
+) -> Result<(), String> {
+    let mut config_manager = state.config_manager.lock().await;
+    let secure_storage = state.secure_storage.lock().await;
+    
+    match &mut proxy {
+        Some(proxy) => proxy.store_password(&secure_storage, PROXY_PASSWORD_KEY)
+            .map_err(|e| format!("Failed to store proxy password: {}", e))?,
+        None => {
+            secure_storage.delete(PROXY_PASSWORD_KEY).ok();
+        }
+    }
+    
+    // Applies to connections opened from now on
+    config_manager.update_config(|config| config.proxy = proxy)
+        .map_err(|e| format!("Failed to save config: {}", e))
+}
+
+#[tauri::command]
+async fn save_session(
+    state: State<'_, AppState>,
+    name: String,
+    config: ConnectionConfig,
 ) -> Result<(), String> {
     let mut session_manager = state.session_manager.lock().await;
     let secure_storage = state.secure_storage.lock().await;
     
-    let config = ConnectionConfig {
-        host,
-        port,
-        username: username.clone(),
-        auth_type: auth_type.clone(),
-        auth_value: auth_value.clone(),
+    let mut config = ConnectionConfig {
+        group: config.group.filter(|g| !g.is_empty()),
+        charset: config.charset.filter(|c| !c.is_empty()),
+        ..config
     };
     
-    let encrypted_auth = secure_storage.encrypt(&auth_value)
+    let proxy_password_key = session_proxy_password_key(&name);
+    match &mut config.proxy {
+        ProxySetting::Custom(proxy) => proxy.store_password(&secure_storage, &proxy_password_key)
+            .map_err(|e| format!("Failed to store proxy password: {}", e))?,
+        _ => {
+            secure_storage.delete(&proxy_password_key).ok();
+        }
+    }
+    
+    let encrypted_auth = secure_storage.encrypt(&config.auth_value)
         .map_err(|e| format!("Encryption failed: {}", e))?;
     
     match session_manager.save_session(&name, config, encrypted_auth).await {
@@ -136,6 +684,13 @@ async fn load_sessions(
                         "port": config.port,
                         "username": config.username,
                         "auth_type": config.auth_type,
+                        "jump_hosts": config.jump_hosts,
+                        "group": config.group,
+                        "charset": config.charset,
+                        "forward_agent": config.forward_agent,
+                        "forward_x11": config.forward_x11,
+                        "algorithms": config.algorithms,
+                        "proxy": config.proxy,
                     })
                 })
                 .collect();
@@ -153,7 +708,11 @@ async fn delete_session(
     let mut session_manager = state.session_manager.lock().await;
     
     match session_manager.delete_session(&name).await {
-        Ok(_) => Ok(()),
+        Ok(_) => {
+            let secure_storage = state.secure_storage.lock().await;
+            secure_storage.delete(&session_proxy_password_key(&name)).ok();
+            Ok(())
+        }
         Err(e) => Err(format!("Delete failed: {}", e)),
     }
 }
@@ -181,6 +740,15 @@ fn get_app_version() -> String {
     env!("CARGO_PKG_VERSION").to_string()
 }
 
+#[tauri::command]
+async fn get_negotiated_algorithms(
+    state: State<'_, AppState>,
+    session_id: String,
+) -> Result<NegotiatedMethods, String> {
+    state.ssh_manager.negotiated_methods(&session_id).await
+        .map_err(|e| format!("Failed to read algorithms: {}", e))
+}
+
 #[tauri::command]
 async fn resize_terminal(
     state: State<'_, AppState>,
@@ -188,9 +756,7 @@ async fn resize_terminal(
     cols: u32,
     rows: u32,
 ) -> Result<(), String> {
-    let manager = state.ssh_manager.lock().await;
-    
-    match manager.resize_terminal(&session_id, cols, rows).await {
+    match state.ssh_manager.resize_terminal(&session_id, cols, rows).await {
         Ok(_) => Ok(()),
         Err(e) => Err(format!("Resize failed: {}", e)),
     }
@@ -350,6 +916,13 @@ async fn import_from_vault(
         username: username.clone(),
         auth_type: auth_type.clone(),
         auth_value: String::new(),
+        jump_hosts: Vec::new(),
+        group: None,
+        charset: None,
+        forward_agent: None,
+        forward_x11: false,
+        algorithms: AlgorithmPreferences::default(),
+        proxy: ProxySetting::default(),
     };
     
     let secure_storage = state.secure_storage.lock().await;
@@ -384,6 +957,13 @@ async fn ensure_default_sessions(
         username: "rileyseaburg".to_string(),
         auth_type: "password".to_string(),
         auth_value: String::new(),
+        jump_hosts: Vec::new(),
+        group: None,
+        charset: None,
+        forward_agent: None,
+        forward_x11: false,
+        algorithms: AlgorithmPreferences::default(),
+        proxy: ProxySetting::default(),
     };
     
     let secure_storage = state.secure_storage.lock().await;
@@ -422,25 +1002,39 @@ pub fn run() {
         }
     };
     
-    let app_state = AppState {
-        ssh_manager: Arc::new(Mutex::new(SshManager::new())),
-        session_manager: Arc::new(Mutex::new(SessionManager::new())),
-        secure_storage: Arc::new(Mutex::new(secure_storage)),
+    let config_manager = match ConfigManager::new() {
+        Ok(manager) => manager,
+        Err(e) => {
+            eprintln!("Warning: Failed to load config: {}. Using defaults.", e);
+            ConfigManager::new_default()
+        }
     };
 
+    let transfer_limit = config_manager.get_config().transfers.max_concurrent;
+
     tauri::Builder::default()
-        .manage(app_state)
         .invoke_handler(tauri::generate_handler![
             connect_ssh,
+            cancel_connect,
+            open_shell_channel,
             disconnect_ssh,
             send_command,
-            read_output,
+            get_broadcast_group,
+            set_broadcast_group,
+            add_to_broadcast,
+            remove_from_broadcast,
+            exec_command,
+            run_fanout,
+            get_fanout_run,
+            export_fanout_run,
+            remove_fanout_run,
             save_session,
             load_sessions,
             delete_session,
             get_session_credentials,
             get_app_version,
             resize_terminal,
+            get_negotiated_algorithms,
             generate_ssh_key,
             save_ssh_key,
             load_ssh_key,
@@ -448,8 +1042,53 @@ pub fn run() {
             delete_ssh_key,
             import_from_vault,
             ensure_default_sessions,
+            respond_prompt,
+            start_local_forward,
+            start_remote_forward,
+            start_dynamic_forward,
+            list_forwards,
+            stop_forward,
+            sftp_list,
+            sftp_stat,
+            sftp_mkdir,
+            sftp_rename,
+            sftp_delete,
+            sftp_chmod,
+            sftp_upload,
+            sftp_download,
+            list_transfers,
+            cancel_transfer,
+            retry_transfer,
+            clear_finished_transfers,
+            list_known_hosts,
+            remove_known_host,
+            clear_passphrase_cache,
+            get_app_config,
+            update_security_config,
+            update_transfer_config,
+            update_keepalive_config,
+            update_reconnect_config,
+            update_timeout_config,
+            update_proxy_config,
         ])
-        .setup(|app| {
+        .setup(move |app| {
+            let prompts = Arc::new(PromptBroker::new(app.handle().clone()));
+            let secure_storage = Arc::new(Mutex::new(secure_storage));
+            
+            app.manage(AppState {
+                ssh_manager: Arc::new(SshManager::new(
+                    app.handle().clone(),
+                    prompts.clone(),
+                    secure_storage.clone(),
+                    transfer_limit,
+                )),
+                session_manager: Arc::new(Mutex::new(SessionManager::new())),
+                secure_storage,
+                config_manager: Arc::new(Mutex::new(config_manager)),
+                prompts,
+                fanout: Arc::new(FanoutManager::new(app.handle().clone())),
+            });
+            
             #[cfg(debug_assertions)]
             {
                 let window = app.get_webview_window("main").unwrap();
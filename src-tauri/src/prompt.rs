@@ -0,0 +1,64 @@
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::mpsc;
+use std::sync::Mutex;
+use std::time::Duration;
+use tauri::{AppHandle, Emitter};
+use log::warn;
+
+/// How long a blocking SSH task waits for the user before giving up.
+const PROMPT_TIMEOUT: Duration = Duration::from_secs(300);
+
+#[derive(Clone, Serialize)]
+struct PromptEvent<P: Serialize + Clone> {
+    id: String,
+    #[serde(flatten)]
+    payload: P,
+}
+
+/// Round-trips questions from blocking SSH tasks to the webview.
+///
+/// `ask` emits an event carrying a fresh prompt id and blocks the calling
+/// thread until the frontend answers through the `respond_prompt` command.
+pub struct PromptBroker {
+    app: AppHandle,
+    pending: Mutex<HashMap<String, mpsc::Sender<serde_json::Value>>>,
+}
+
+impl PromptBroker {
+    pub fn new(app: AppHandle) -> Self {
+        Self {
+            app,
+            pending: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Emits `event` and waits for the reply. Returns `None` if the event
+    /// could not be delivered or the user did not answer in time.
+    ///
+    /// Must only be called from a blocking context (e.g. `spawn_blocking`).
+    pub fn ask<P: Serialize + Clone>(&self, event: &str, payload: P) -> Option<serde_json::Value> {
+        let id = uuid::Uuid::new_v4().to_string();
+        let (tx, rx) = mpsc::channel();
+        self.pending.lock().unwrap().insert(id.clone(), tx);
+
+        let event_payload = PromptEvent { id: id.clone(), payload };
+        if let Err(e) = self.app.emit(event, event_payload) {
+            warn!("Failed to emit {} prompt: {}", event, e);
+            self.pending.lock().unwrap().remove(&id);
+            return None;
+        }
+
+        let reply = rx.recv_timeout(PROMPT_TIMEOUT).ok();
+        self.pending.lock().unwrap().remove(&id);
+        reply
+    }
+
+    /// Delivers the frontend's answer to a pending prompt.
+    pub fn respond(&self, id: &str, reply: serde_json::Value) -> bool {
+        match self.pending.lock().unwrap().remove(id) {
+            Some(tx) => tx.send(reply).is_ok(),
+            None => false,
+        }
+    }
+}
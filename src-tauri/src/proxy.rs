@@ -0,0 +1,174 @@
+use std::io::{self, ErrorKind, Read, Write};
+use std::net::TcpStream;
+
+use crate::config::{ProxyConfig, ProxyKind};
+use crate::crypto::base64;
+use crate::socks;
+
+/// Longest response header accepted from an HTTP proxy.
+const MAX_RESPONSE_LEN: usize = 16 * 1024;
+
+/// Asks the proxy `stream` is connected to for a tunnel to `host:port`. On
+/// success the stream carries the tunnelled connection.
+pub fn open_tunnel(stream: &mut TcpStream, proxy: &ProxyConfig, host: &str, port: u16) -> io::Result<()> {
+    match proxy.kind {
+        ProxyKind::Http => http_connect(stream, host, port, proxy.credentials()),
+        ProxyKind::Socks5 => socks::connect(stream, host, port, proxy.credentials()),
+    }
+}
+
+fn http_connect(
+    stream: &mut (impl Read + Write),
+    host: &str,
+    port: u16,
+    credentials: Option<(&str, &str)>,
+) -> io::Result<()> {
+    let authority = if host.contains(':') {
+        format!("[{}]:{}", host, port)
+    } else {
+        format!("{}:{}", host, port)
+    };
+    let mut request = format!("CONNECT {0} HTTP/1.1\r\nHost: {0}\r\n", authority);
+    if let Some((username, password)) = credentials {
+        let token = base64::encode(format!("{}:{}", username, password));
+        request.push_str(&format!("Proxy-Authorization: Basic {}\r\n", token));
+    }
+    request.push_str("\r\n");
+    stream.write_all(request.as_bytes())?;
+
+    // A byte at a time: the server's SSH banner may follow the header at once
+    let mut response = Vec::new();
+    let mut byte = [0u8; 1];
+    while !response.ends_with(b"\r\n\r\n") {
+        if response.len() >= MAX_RESPONSE_LEN {
+            return Err(io::Error::new(ErrorKind::InvalidData, "proxy response too long"));
+        }
+        stream.read_exact(&mut byte)?;
+        response.push(byte[0]);
+    }
+
+    let response = String::from_utf8_lossy(&response);
+    let status_line = response.lines().next().unwrap_or_default().trim();
+    match status_line.split_whitespace().nth(1) {
+        Some(status) if status.len() == 3 && status.starts_with('2') => Ok(()),
+        Some("407") => Err(io::Error::new(
+            ErrorKind::PermissionDenied,
+            if credentials.is_some() { "proxy rejected the credentials" } else { "proxy requires authentication" },
+        )),
+        _ => Err(io::Error::new(
+            ErrorKind::ConnectionRefused,
+            format!("proxy answered \"{}\"", status_line),
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::socks::tests::Scripted;
+
+    const BANNER: &[u8] = b"SSH-2.0-OpenSSH_9.6\r\n";
+
+    fn response(head: &str) -> Scripted {
+        Scripted::new(&[head.as_bytes(), BANNER].concat())
+    }
+
+    fn sent(stream: &Scripted) -> String {
+        String::from_utf8(stream.output.clone()).unwrap()
+    }
+
+    #[test]
+    fn tunnel_established() {
+        let mut stream = response("HTTP/1.1 200 Connection established\r\nVia: proxy\r\n\r\n");
+        http_connect(&mut stream, "example.com", 22, None).unwrap();
+
+        assert_eq!(sent(&stream), "CONNECT example.com:22 HTTP/1.1\r\nHost: example.com:22\r\n\r\n");
+        assert_eq!(stream.unread(), BANNER);
+    }
+
+    #[test]
+    fn any_2xx_status_is_success() {
+        let mut stream = response("HTTP/1.0 204 No Content\r\n\r\n");
+        http_connect(&mut stream, "example.com", 22, None).unwrap();
+    }
+
+    #[test]
+    fn ipv6_target_is_bracketed() {
+        let mut stream = response("HTTP/1.1 200 OK\r\n\r\n");
+        http_connect(&mut stream, "2001:db8::1", 2222, None).unwrap();
+
+        assert!(sent(&stream).starts_with("CONNECT [2001:db8::1]:2222 HTTP/1.1\r\nHost: [2001:db8::1]:2222\r\n"));
+    }
+
+    #[test]
+    fn credentials_sent_as_basic_auth() {
+        let mut stream = response("HTTP/1.1 200 OK\r\n\r\n");
+        http_connect(&mut stream, "example.com", 22, Some(("user", "secret"))).unwrap();
+
+        let expected = format!("Proxy-Authorization: Basic {}\r\n\r\n", base64::encode("user:secret"));
+        assert!(sent(&stream).ends_with(&expected), "{}", sent(&stream));
+    }
+
+    #[test]
+    fn auth_required_without_credentials() {
+        let mut stream = response("HTTP/1.1 407 Proxy Authentication Required\r\nProxy-Authenticate: Basic\r\n\r\n");
+        let e = http_connect(&mut stream, "example.com", 22, None).unwrap_err();
+
+        assert_eq!(e.kind(), ErrorKind::PermissionDenied);
+        assert_eq!(e.to_string(), "proxy requires authentication");
+    }
+
+    #[test]
+    fn credentials_rejected() {
+        let mut stream = response("HTTP/1.1 407 Proxy Authentication Required\r\n\r\n");
+        let e = http_connect(&mut stream, "example.com", 22, Some(("user", "wrong"))).unwrap_err();
+
+        assert_eq!(e.kind(), ErrorKind::PermissionDenied);
+        assert_eq!(e.to_string(), "proxy rejected the credentials");
+    }
+
+    #[test]
+    fn refusal_reports_status_line() {
+        let mut stream = response("HTTP/1.1 403 Forbidden\r\nContent-Length: 0\r\n\r\n");
+        let e = http_connect(&mut stream, "example.com", 22, None).unwrap_err();
+
+        assert_eq!(e.kind(), ErrorKind::ConnectionRefused);
+        assert_eq!(e.to_string(), "proxy answered \"HTTP/1.1 403 Forbidden\"");
+    }
+
+    #[test]
+    fn malformed_status_line_is_refusal() {
+        for head in ["HTTP/1.1 2000 OK\r\n\r\n", "HTTP/1.1\r\n\r\n", "\r\n\r\n"] {
+            let mut stream = response(head);
+            let e = http_connect(&mut stream, "example.com", 22, None).unwrap_err();
+
+            assert_eq!(e.kind(), ErrorKind::ConnectionRefused, "{:?}", head);
+        }
+    }
+
+    #[test]
+    fn header_read_up_to_blank_line_only() {
+        // A bare LF doesn't end the header, so the CRLF pair is what counts
+        let mut stream = response("HTTP/1.1 200 OK\r\nX-Note: a\n\nb\r\n\r\n");
+        http_connect(&mut stream, "example.com", 22, None).unwrap();
+
+        assert_eq!(stream.unread(), BANNER);
+    }
+
+    #[test]
+    fn overlong_response_rejected() {
+        let head = format!("HTTP/1.1 200 OK\r\nX-Padding: {}\r\n\r\n", "a".repeat(MAX_RESPONSE_LEN));
+        let mut stream = response(&head);
+        let e = http_connect(&mut stream, "example.com", 22, None).unwrap_err();
+
+        assert_eq!(e.kind(), ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn connection_closed_mid_header() {
+        let mut stream = Scripted::new(b"HTTP/1.1 200 OK\r\n");
+        let e = http_connect(&mut stream, "example.com", 22, None).unwrap_err();
+
+        assert_eq!(e.kind(), ErrorKind::UnexpectedEof);
+    }
+}
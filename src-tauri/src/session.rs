@@ -3,6 +3,10 @@ use std::collections::HashMap;
 use std::path::PathBuf;
 use anyhow::Result;
 
+use crate::agent::AgentSource;
+use crate::algorithms::AlgorithmPreferences;
+use crate::config::ProxyConfig;
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ConnectionConfig {
     pub host: String,
@@ -10,6 +14,57 @@ pub struct ConnectionConfig {
     pub username: String,
     pub auth_type: String,
     pub auth_value: String,
+    /// Saved session names to hop through, in order (like `ProxyJump`).
+    #[serde(default)]
+    pub jump_hosts: Vec<String>,
+    /// Free-form label for running commands across related sessions.
+    #[serde(default)]
+    pub group: Option<String>,
+    /// Remote character set, e.g. `"shift_jis"`; UTF-8 when unset.
+    #[serde(default)]
+    pub charset: Option<String>,
+    /// Forwards an agent to the server, served from the given source; off when unset.
+    #[serde(default)]
+    pub forward_agent: Option<AgentSource>,
+    /// Forwards X11 connections from shells to the local `DISPLAY`.
+    #[serde(default)]
+    pub forward_x11: bool,
+    /// Algorithms to offer in the handshake; libssh2's defaults when empty.
+    #[serde(default)]
+    pub algorithms: AlgorithmPreferences,
+    /// How the first host is reached: through the app's proxy by default.
+    #[serde(default)]
+    pub proxy: ProxySetting,
+}
+
+/// A session's choice of outbound proxy.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(tag = "mode", rename_all = "lowercase")]
+pub enum ProxySetting {
+    /// Whatever `AppConfig::proxy` says.
+    #[default]
+    Global,
+    /// Connect directly, even if the app has a proxy set.
+    Direct,
+    Custom(ProxyConfig),
+}
+
+impl ProxySetting {
+    /// The proxy to use, given the app-wide one.
+    pub fn resolve(&self, global: Option<&ProxyConfig>) -> Option<ProxyConfig> {
+        match self {
+            ProxySetting::Global => global.cloned(),
+            ProxySetting::Direct => None,
+            ProxySetting::Custom(proxy) => Some(proxy.clone()),
+        }
+    }
+}
+
+/// A resolved jump host: the saved session's config with its decrypted credentials.
+#[derive(Debug, Clone)]
+pub struct JumpHost {
+    pub name: String,
+    pub config: ConnectionConfig,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -34,7 +89,7 @@ impl SessionManager {
         }
     }
 
-    fn get_config_dir() -> PathBuf {
+    pub fn get_config_dir() -> PathBuf {
         // On iOS, use the app's documents directory (sandbox-friendly)
         #[cfg(target_os = "ios")]
         {
@@ -100,6 +155,20 @@ impl SessionManager {
         }
     }
 
+    /// Names of the saved sessions in `group`.
+    pub async fn sessions_in_group(&self, group: &str) -> Result<Vec<String>> {
+        let sessions = self.load_sessions_from_disk().await?;
+        
+        let mut names: Vec<String> = sessions
+            .into_iter()
+            .filter(|(_, session)| session.config.group.as_deref() == Some(group))
+            .map(|(name, _)| name)
+            .collect();
+        names.sort();
+        
+        Ok(names)
+    }
+
     pub async fn delete_session(&mut self, name: &str) -> Result<()> {
         self.load_sessions_from_disk().await?;
         
@@ -0,0 +1,247 @@
+use serde::Serialize;
+use ssh2::{FileStat, FileType, OpenFlags, OpenType, Sftp};
+use std::fmt::Display;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::Path;
+use std::sync::Mutex;
+
+use crate::ssh::{SshConnection, SshError};
+
+/// Bytes moved per locked SFTP call during a transfer. Small enough that the
+/// terminal on the same session stays responsive.
+const TRANSFER_CHUNK: usize = 32 * 1024;
+/// Mode for directories created without an explicit one.
+pub const DEFAULT_DIR_MODE: u32 = 0o755;
+
+#[derive(Debug, Clone, Copy, Serialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum EntryKind {
+    File,
+    Directory,
+    Symlink,
+    Other,
+}
+
+/// A remote file as shown in the file browser.
+#[derive(Debug, Clone, Serialize)]
+pub struct SftpEntry {
+    pub name: String,
+    pub path: String,
+    pub kind: EntryKind,
+    pub size: u64,
+    /// Permission bits only (e.g. `0o644`), without the file type.
+    pub mode: u32,
+    /// Seconds since the Unix epoch.
+    pub mtime: Option<u64>,
+    pub symlink_target: Option<String>,
+}
+
+fn sftp_error(path: &Path, e: impl Display) -> SshError {
+    SshError::SftpFailed(format!("{}: {}", path.display(), e))
+}
+
+fn entry(sftp: &Sftp, path: &Path, stat: &FileStat) -> SftpEntry {
+    let kind = match stat.file_type() {
+        FileType::RegularFile => EntryKind::File,
+        FileType::Directory => EntryKind::Directory,
+        FileType::Symlink => EntryKind::Symlink,
+        _ => EntryKind::Other,
+    };
+    let symlink_target = if kind == EntryKind::Symlink {
+        sftp.readlink(path).ok().map(|target| target.to_string_lossy().into_owned())
+    } else {
+        None
+    };
+
+    SftpEntry {
+        name: path
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_else(|| path.to_string_lossy().into_owned()),
+        path: path.to_string_lossy().into_owned(),
+        kind,
+        size: stat.size.unwrap_or(0),
+        mode: stat.perm.unwrap_or(0) & 0o7777,
+        mtime: stat.mtime,
+        symlink_target,
+    }
+}
+
+/// Lists a directory, directories first and then by name.
+pub fn list(sftp: &Sftp, path: &Path) -> Result<Vec<SftpEntry>, SshError> {
+    let mut entries: Vec<SftpEntry> = sftp
+        .readdir(path)
+        .map_err(|e| sftp_error(path, e))?
+        .iter()
+        .map(|(entry_path, stat)| entry(sftp, entry_path, stat))
+        .collect();
+
+    entries.sort_by(|a, b| {
+        (b.kind == EntryKind::Directory)
+            .cmp(&(a.kind == EntryKind::Directory))
+            .then_with(|| a.name.cmp(&b.name))
+    });
+    Ok(entries)
+}
+
+/// Describes `path` itself; symlinks are not followed.
+pub fn stat(sftp: &Sftp, path: &Path) -> Result<SftpEntry, SshError> {
+    let stat = sftp.lstat(path).map_err(|e| sftp_error(path, e))?;
+    Ok(entry(sftp, path, &stat))
+}
+
+pub fn mkdir(sftp: &Sftp, path: &Path, mode: u32) -> Result<(), SshError> {
+    sftp.mkdir(path, mode as i32).map_err(|e| sftp_error(path, e))
+}
+
+pub fn rename(sftp: &Sftp, from: &Path, to: &Path) -> Result<(), SshError> {
+    sftp.rename(from, to, None).map_err(|e| sftp_error(from, e))
+}
+
+/// Removes a file, a symlink or an empty directory.
+pub fn delete(sftp: &Sftp, path: &Path) -> Result<(), SshError> {
+    let stat = sftp.lstat(path).map_err(|e| sftp_error(path, e))?;
+    if stat.is_dir() {
+        sftp.rmdir(path).map_err(|e| sftp_error(path, e))
+    } else {
+        sftp.unlink(path).map_err(|e| sftp_error(path, e))
+    }
+}
+
+pub fn chmod(sftp: &Sftp, path: &Path, mode: u32) -> Result<(), SshError> {
+    let stat = FileStat {
+        size: None,
+        uid: None,
+        gid: None,
+        perm: Some(mode & 0o7777),
+        atime: None,
+        mtime: None,
+    };
+    sftp.setstat(path, stat).map_err(|e| sftp_error(path, e))
+}
+
+/// Called before the first chunk and after every chunk of a transfer with the
+/// bytes done so far (including any resumed offset) and the file size.
+/// Returning `false` cancels the transfer.
+pub type ProgressFn<'a> = dyn FnMut(u64, u64) -> bool + 'a;
+
+/// Copies a local file to `remote_path`. Returns the file size.
+///
+/// With `resume`, a shorter file already at `remote_path` is taken as an
+/// earlier partial upload and only the rest is sent; otherwise it is replaced.
+/// The connection is locked per chunk rather than for the whole transfer, so
+/// the shell and forwards on the same session keep running.
+pub fn upload(
+    connection: &Mutex<SshConnection>,
+    local_path: &Path,
+    remote_path: &Path,
+    resume: bool,
+    progress: &mut ProgressFn,
+) -> Result<u64, SshError> {
+    let mut local = std::fs::File::open(local_path).map_err(|e| sftp_error(local_path, e))?;
+    let total = local.metadata().map_err(|e| sftp_error(local_path, e))?.len();
+
+    let (mut remote, offset) = {
+        let mut conn = connection.lock().unwrap();
+        let sftp = conn.sftp()?;
+        let existing = if resume {
+            sftp.stat(remote_path).ok().and_then(|stat| stat.size).filter(|&size| size <= total)
+        } else {
+            None
+        };
+        match existing {
+            Some(offset) => {
+                let mut remote = sftp
+                    .open_mode(remote_path, OpenFlags::WRITE, 0o644, OpenType::File)
+                    .map_err(|e| sftp_error(remote_path, e))?;
+                remote.seek(SeekFrom::Start(offset)).map_err(|e| sftp_error(remote_path, e))?;
+                (remote, offset)
+            }
+            None => (sftp.create(remote_path).map_err(|e| sftp_error(remote_path, e))?, 0),
+        }
+    };
+
+    let result = (|| {
+        local.seek(SeekFrom::Start(offset)).map_err(|e| sftp_error(local_path, e))?;
+        let mut buffer = vec![0u8; TRANSFER_CHUNK];
+        let mut done = offset;
+        loop {
+            if !progress(done, total) {
+                return Err(SshError::TransferCancelled);
+            }
+            let n = local.read(&mut buffer).map_err(|e| sftp_error(local_path, e))?;
+            if n == 0 {
+                return Ok(done);
+            }
+            let _conn = connection.lock().unwrap();
+            remote.write_all(&buffer[..n]).map_err(|e| sftp_error(remote_path, e))?;
+            done += n as u64;
+        }
+    })();
+
+    // Closing the handle is a round-trip on the session too
+    let _conn = connection.lock().unwrap();
+    drop(remote);
+    result
+}
+
+/// Copies `remote_path` to a local file. Returns the file size.
+///
+/// With `resume`, a shorter file already at `local_path` is taken as an
+/// earlier partial download and only the rest is fetched.
+pub fn download(
+    connection: &Mutex<SshConnection>,
+    remote_path: &Path,
+    local_path: &Path,
+    resume: bool,
+    progress: &mut ProgressFn,
+) -> Result<u64, SshError> {
+    let (mut remote, total) = {
+        let mut conn = connection.lock().unwrap();
+        let mut remote = conn.sftp()?
+            .open(remote_path)
+            .map_err(|e| sftp_error(remote_path, e))?;
+        let total = remote.stat().map_err(|e| sftp_error(remote_path, e))?.size.unwrap_or(0);
+        (remote, total)
+    };
+
+    let result = (|| {
+        let existing = if resume {
+            std::fs::metadata(local_path).ok().map(|m| m.len()).filter(|&size| size <= total)
+        } else {
+            None
+        };
+        let (mut local, offset) = match existing {
+            Some(offset) => {
+                let local = std::fs::OpenOptions::new()
+                    .append(true)
+                    .open(local_path)
+                    .map_err(|e| sftp_error(local_path, e))?;
+                (local, offset)
+            }
+            None => (std::fs::File::create(local_path).map_err(|e| sftp_error(local_path, e))?, 0),
+        };
+        remote.seek(SeekFrom::Start(offset)).map_err(|e| sftp_error(remote_path, e))?;
+
+        let mut buffer = vec![0u8; TRANSFER_CHUNK];
+        let mut done = offset;
+        loop {
+            if !progress(done, total) {
+                return Err(SshError::TransferCancelled);
+            }
+            let n = {
+                let _conn = connection.lock().unwrap();
+                remote.read(&mut buffer).map_err(|e| sftp_error(remote_path, e))?
+            };
+            if n == 0 {
+                return Ok(done);
+            }
+            local.write_all(&buffer[..n]).map_err(|e| sftp_error(local_path, e))?;
+            done += n as u64;
+        }
+    })();
+
+    let _conn = connection.lock().unwrap();
+    drop(remote);
+    result
+}
@@ -0,0 +1,478 @@
+use std::io::{self, Read, Write};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+const SOCKS_VERSION: u8 = 5;
+const METHOD_NO_AUTH: u8 = 0x00;
+const METHOD_USERNAME_PASSWORD: u8 = 0x02;
+const METHOD_NONE_ACCEPTABLE: u8 = 0xff;
+const CMD_CONNECT: u8 = 0x01;
+const ATYP_IPV4: u8 = 0x01;
+const ATYP_DOMAIN: u8 = 0x03;
+const ATYP_IPV6: u8 = 0x04;
+/// Version of the username/password subnegotiation (RFC 1929).
+const AUTH_VERSION: u8 = 0x01;
+
+/// SOCKS5 reply codes (RFC 1928, section 6).
+#[derive(Debug, Clone, Copy)]
+pub enum Reply {
+    Succeeded = 0x00,
+    HostUnreachable = 0x04,
+    CommandNotSupported = 0x07,
+    AddressTypeNotSupported = 0x08,
+}
+
+/// Runs the server side of a SOCKS5 handshake up to the request.
+///
+/// Only the CONNECT command without authentication is supported. Returns the
+/// requested destination; the caller must answer with `send_reply`.
+pub fn accept_connect(stream: &mut (impl Read + Write)) -> io::Result<(String, u16)> {
+    let mut header = [0u8; 2];
+    stream.read_exact(&mut header)?;
+    if header[0] != SOCKS_VERSION {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "not a SOCKS5 client"));
+    }
+
+    let mut methods = vec![0u8; header[1] as usize];
+    stream.read_exact(&mut methods)?;
+    if !methods.contains(&METHOD_NO_AUTH) {
+        stream.write_all(&[SOCKS_VERSION, METHOD_NONE_ACCEPTABLE])?;
+        return Err(io::Error::new(io::ErrorKind::PermissionDenied, "client requires authentication"));
+    }
+    stream.write_all(&[SOCKS_VERSION, METHOD_NO_AUTH])?;
+
+    let mut request = [0u8; 4];
+    stream.read_exact(&mut request)?;
+    if request[1] != CMD_CONNECT {
+        send_reply(stream, Reply::CommandNotSupported)?;
+        return Err(io::Error::new(io::ErrorKind::Unsupported, "only CONNECT is supported"));
+    }
+
+    let host = match request[3] {
+        ATYP_IPV4 => {
+            let mut addr = [0u8; 4];
+            stream.read_exact(&mut addr)?;
+            Ipv4Addr::from(addr).to_string()
+        }
+        ATYP_DOMAIN => {
+            let mut len = [0u8; 1];
+            stream.read_exact(&mut len)?;
+            let mut name = vec![0u8; len[0] as usize];
+            stream.read_exact(&mut name)?;
+            String::from_utf8(name)
+                .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "invalid domain name"))?
+        }
+        ATYP_IPV6 => {
+            let mut addr = [0u8; 16];
+            stream.read_exact(&mut addr)?;
+            Ipv6Addr::from(addr).to_string()
+        }
+        _ => {
+            send_reply(stream, Reply::AddressTypeNotSupported)?;
+            return Err(io::Error::new(io::ErrorKind::Unsupported, "unknown address type"));
+        }
+    };
+
+    let mut port = [0u8; 2];
+    stream.read_exact(&mut port)?;
+
+    Ok((host, u16::from_be_bytes(port)))
+}
+
+/// Answers a CONNECT request. The bound address is not meaningful for a
+/// tunnelled connection, so it is always reported as 0.0.0.0:0.
+pub fn send_reply(stream: &mut impl Write, reply: Reply) -> io::Result<()> {
+    stream.write_all(&[SOCKS_VERSION, reply as u8, 0x00, ATYP_IPV4, 0, 0, 0, 0, 0, 0])
+}
+
+/// Runs the client side of a SOCKS5 CONNECT to `host:port` over `stream`,
+/// a connection to the proxy. Hostnames are left for the proxy to resolve.
+/// On success the stream carries the tunnelled connection.
+///
+/// With `credentials`, username/password authentication is offered alongside
+/// none, and whichever the proxy picks is used.
+pub fn connect(
+    stream: &mut (impl Read + Write),
+    host: &str,
+    port: u16,
+    credentials: Option<(&str, &str)>,
+) -> io::Result<()> {
+    let methods: &[u8] = if credentials.is_some() {
+        &[METHOD_NO_AUTH, METHOD_USERNAME_PASSWORD]
+    } else {
+        &[METHOD_NO_AUTH]
+    };
+    let greeting = [&[SOCKS_VERSION, methods.len() as u8][..], methods].concat();
+    stream.write_all(&greeting)?;
+    let mut choice = [0u8; 2];
+    stream.read_exact(&mut choice)?;
+    if choice[0] != SOCKS_VERSION {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "not a SOCKS5 proxy"));
+    }
+    if !methods.contains(&choice[1]) {
+        return Err(io::Error::new(io::ErrorKind::PermissionDenied, "proxy refused the authentication method"));
+    }
+
+    if let Some((username, password)) = credentials.filter(|_| choice[1] == METHOD_USERNAME_PASSWORD) {
+        let too_long = || io::Error::new(io::ErrorKind::InvalidInput, "username or password too long");
+        let mut auth = vec![AUTH_VERSION];
+        auth.push(u8::try_from(username.len()).map_err(|_| too_long())?);
+        auth.extend_from_slice(username.as_bytes());
+        auth.push(u8::try_from(password.len()).map_err(|_| too_long())?);
+        auth.extend_from_slice(password.as_bytes());
+        stream.write_all(&auth)?;
+
+        let mut status = [0u8; 2];
+        stream.read_exact(&mut status)?;
+        if status[0] != AUTH_VERSION {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "unexpected authentication reply"));
+        }
+        if status[1] != 0 {
+            return Err(io::Error::new(io::ErrorKind::PermissionDenied, "proxy rejected the credentials"));
+        }
+    }
+
+    let mut request = vec![SOCKS_VERSION, CMD_CONNECT, 0x00];
+    match host.parse::<IpAddr>() {
+        Ok(IpAddr::V4(addr)) => {
+            request.push(ATYP_IPV4);
+            request.extend_from_slice(&addr.octets());
+        }
+        Ok(IpAddr::V6(addr)) => {
+            request.push(ATYP_IPV6);
+            request.extend_from_slice(&addr.octets());
+        }
+        Err(_) => {
+            let len = u8::try_from(host.len())
+                .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "hostname too long"))?;
+            request.push(ATYP_DOMAIN);
+            request.push(len);
+            request.extend_from_slice(host.as_bytes());
+        }
+    }
+    request.extend_from_slice(&port.to_be_bytes());
+    stream.write_all(&request)?;
+
+    let mut reply = [0u8; 4];
+    stream.read_exact(&mut reply)?;
+    if reply[1] != Reply::Succeeded as u8 {
+        return Err(io::Error::new(
+            io::ErrorKind::ConnectionRefused,
+            format!("proxy could not connect ({})", reply_message(reply[1])),
+        ));
+    }
+
+    // The bound address that follows isn't needed, but must not be left for SSH
+    let addr_len = match reply[3] {
+        ATYP_IPV4 => 4,
+        ATYP_IPV6 => 16,
+        ATYP_DOMAIN => {
+            let mut len = [0u8; 1];
+            stream.read_exact(&mut len)?;
+            len[0] as usize
+        }
+        _ => return Err(io::Error::new(io::ErrorKind::InvalidData, "unknown address type in reply")),
+    };
+    let mut bound = vec![0u8; addr_len + 2];
+    stream.read_exact(&mut bound)
+}
+
+/// RFC 1928's wording for a reply code.
+fn reply_message(code: u8) -> &'static str {
+    match code {
+        0x01 => "general failure",
+        0x02 => "not allowed by ruleset",
+        0x03 => "network unreachable",
+        0x04 => "host unreachable",
+        0x05 => "connection refused",
+        0x06 => "TTL expired",
+        0x07 => "command not supported",
+        0x08 => "address type not supported",
+        _ => "unknown error",
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use std::io::{Cursor, ErrorKind};
+
+    /// A peer that answers with a fixed script and records what it is sent.
+    pub(crate) struct Scripted {
+        input: Cursor<Vec<u8>>,
+        pub(crate) output: Vec<u8>,
+    }
+
+    impl Scripted {
+        pub(crate) fn new(input: &[u8]) -> Self {
+            Self {
+                input: Cursor::new(input.to_vec()),
+                output: Vec::new(),
+            }
+        }
+
+        /// The part of the script not read yet.
+        pub(crate) fn unread(&self) -> &[u8] {
+            &self.input.get_ref()[self.input.position() as usize..]
+        }
+    }
+
+    impl Read for Scripted {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.input.read(buf)
+        }
+    }
+
+    impl Write for Scripted {
+        fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+            self.output.write(data)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    const BANNER: &[u8] = b"SSH-2.0-OpenSSH_9.6\r\n";
+    /// A successful reply bound to 0.0.0.0:0.
+    const SUCCEEDED_IPV4: [u8; 10] = [5, 0, 0, ATYP_IPV4, 0, 0, 0, 0, 0, 0];
+
+    fn script(parts: &[&[u8]]) -> Scripted {
+        Scripted::new(&parts.concat())
+    }
+
+    #[test]
+    fn connect_to_ipv4_address() {
+        let mut stream = script(&[&[5, METHOD_NO_AUTH], &SUCCEEDED_IPV4, BANNER]);
+        connect(&mut stream, "192.0.2.7", 22, None).unwrap();
+
+        assert_eq!(stream.output, [5, 1, METHOD_NO_AUTH, 5, CMD_CONNECT, 0, ATYP_IPV4, 192, 0, 2, 7, 0, 22]);
+        assert_eq!(stream.unread(), BANNER);
+    }
+
+    #[test]
+    fn connect_to_ipv6_address() {
+        let reply = [&[5, 0, 0, ATYP_IPV6][..], &[0; 18]].concat();
+        let mut stream = script(&[&[5, METHOD_NO_AUTH], &reply, BANNER]);
+        connect(&mut stream, "2001:db8::1", 2222, None).unwrap();
+
+        let mut expected = vec![5, 1, METHOD_NO_AUTH, 5, CMD_CONNECT, 0, ATYP_IPV6];
+        expected.extend_from_slice(&"2001:db8::1".parse::<Ipv6Addr>().unwrap().octets());
+        expected.extend_from_slice(&2222u16.to_be_bytes());
+        assert_eq!(stream.output, expected);
+        assert_eq!(stream.unread(), BANNER);
+    }
+
+    #[test]
+    fn connect_to_hostname_leaves_resolving_to_proxy() {
+        let reply = [5, 0, 0, ATYP_DOMAIN, 5, b'p', b'r', b'o', b'x', b'y', 0x1f, 0x90];
+        let mut stream = script(&[&[5, METHOD_NO_AUTH], &reply, BANNER]);
+        connect(&mut stream, "example.com", 22, None).unwrap();
+
+        let request = [&[5, CMD_CONNECT, 0, ATYP_DOMAIN, 11][..], b"example.com", &[0, 22]].concat();
+        let expected = [&[5, 1, METHOD_NO_AUTH][..], &request].concat();
+        assert_eq!(stream.output, expected);
+        assert_eq!(stream.unread(), BANNER);
+    }
+
+    #[test]
+    fn connect_with_credentials() {
+        let mut stream = script(&[&[5, METHOD_USERNAME_PASSWORD], &[AUTH_VERSION, 0], &SUCCEEDED_IPV4]);
+        connect(&mut stream, "10.0.0.1", 22, Some(("user", "secret"))).unwrap();
+
+        let expected = [
+            &[5, 2, METHOD_NO_AUTH, METHOD_USERNAME_PASSWORD][..],
+            &[AUTH_VERSION, 4],
+            b"user",
+            &[6],
+            b"secret",
+            &[5, CMD_CONNECT, 0, ATYP_IPV4, 10, 0, 0, 1, 0, 22],
+        ]
+        .concat();
+        assert_eq!(stream.output, expected);
+    }
+
+    #[test]
+    fn connect_with_credentials_skips_auth_when_proxy_needs_none() {
+        let mut stream = script(&[&[5, METHOD_NO_AUTH], &SUCCEEDED_IPV4]);
+        connect(&mut stream, "10.0.0.1", 22, Some(("user", "secret"))).unwrap();
+
+        let expected = [
+            &[5, 2, METHOD_NO_AUTH, METHOD_USERNAME_PASSWORD][..],
+            &[5, CMD_CONNECT, 0, ATYP_IPV4, 10, 0, 0, 1, 0, 22],
+        ]
+        .concat();
+        assert_eq!(stream.output, expected);
+    }
+
+    #[test]
+    fn connect_fails_on_unoffered_method() {
+        let mut stream = script(&[&[5, METHOD_USERNAME_PASSWORD]]);
+        let e = connect(&mut stream, "10.0.0.1", 22, None).unwrap_err();
+
+        assert_eq!(e.kind(), ErrorKind::PermissionDenied);
+        assert_eq!(stream.output, [5, 1, METHOD_NO_AUTH]);
+    }
+
+    #[test]
+    fn connect_fails_on_bad_auth_reply_version() {
+        let mut stream = script(&[&[5, METHOD_USERNAME_PASSWORD], &[5, 0], &SUCCEEDED_IPV4]);
+        let e = connect(&mut stream, "10.0.0.1", 22, Some(("user", "secret"))).unwrap_err();
+
+        assert_eq!(e.kind(), ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn connect_fails_on_rejected_credentials() {
+        let mut stream = script(&[&[5, METHOD_USERNAME_PASSWORD], &[AUTH_VERSION, 1]]);
+        let e = connect(&mut stream, "10.0.0.1", 22, Some(("user", "wrong"))).unwrap_err();
+
+        assert_eq!(e.kind(), ErrorKind::PermissionDenied);
+        assert_eq!(e.to_string(), "proxy rejected the credentials");
+    }
+
+    #[test]
+    fn connect_fails_on_refused_method() {
+        let mut stream = script(&[&[5, METHOD_NONE_ACCEPTABLE]]);
+        let e = connect(&mut stream, "10.0.0.1", 22, None).unwrap_err();
+
+        assert_eq!(e.kind(), ErrorKind::PermissionDenied);
+    }
+
+    #[test]
+    fn connect_fails_on_other_protocol() {
+        let mut stream = script(&[b"HTTP/1.1 400 Bad Request\r\n\r\n"]);
+        let e = connect(&mut stream, "10.0.0.1", 22, None).unwrap_err();
+
+        assert_eq!(e.kind(), ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn connect_reports_reply_codes() {
+        for code in 0x01..=0x09 {
+            let mut stream = script(&[&[5, METHOD_NO_AUTH], &[5, code, 0, ATYP_IPV4, 0, 0, 0, 0, 0, 0]]);
+            let e = connect(&mut stream, "10.0.0.1", 22, None).unwrap_err();
+
+            assert_eq!(e.kind(), ErrorKind::ConnectionRefused);
+            assert!(e.to_string().contains(reply_message(code)), "{}", e);
+        }
+    }
+
+    #[test]
+    fn connect_fails_on_unknown_bound_address_type() {
+        let mut stream = script(&[&[5, METHOD_NO_AUTH], &[5, 0, 0, 0x09]]);
+        let e = connect(&mut stream, "10.0.0.1", 22, None).unwrap_err();
+
+        assert_eq!(e.kind(), ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn connect_fails_on_truncated_reply() {
+        let mut stream = script(&[&[5, METHOD_NO_AUTH], &[5, 0, 0, ATYP_IPV4, 0, 0]]);
+        let e = connect(&mut stream, "10.0.0.1", 22, None).unwrap_err();
+
+        assert_eq!(e.kind(), ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn connect_rejects_overlong_credentials() {
+        let username = "u".repeat(256);
+        let mut stream = script(&[&[5, METHOD_USERNAME_PASSWORD]]);
+        let e = connect(&mut stream, "10.0.0.1", 22, Some((&username, "secret"))).unwrap_err();
+
+        assert_eq!(e.kind(), ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn accept_ipv4_request() {
+        let mut stream = script(&[
+            &[5, 2, METHOD_USERNAME_PASSWORD, METHOD_NO_AUTH],
+            &[5, CMD_CONNECT, 0, ATYP_IPV4, 10, 0, 0, 1, 0x1f, 0x90],
+        ]);
+        let target = accept_connect(&mut stream).unwrap();
+
+        assert_eq!(target, ("10.0.0.1".to_string(), 8080));
+        assert_eq!(stream.output, [5, METHOD_NO_AUTH]);
+    }
+
+    #[test]
+    fn accept_domain_request() {
+        let mut stream = script(&[
+            &[5, 1, METHOD_NO_AUTH, 5, CMD_CONNECT, 0, ATYP_DOMAIN, 11],
+            b"example.com",
+            &[1, 187],
+        ]);
+        let target = accept_connect(&mut stream).unwrap();
+
+        assert_eq!(target, ("example.com".to_string(), 443));
+    }
+
+    #[test]
+    fn accept_ipv6_request() {
+        let addr: Ipv6Addr = "2001:db8::1".parse().unwrap();
+        let mut stream = script(&[&[5, 1, METHOD_NO_AUTH, 5, CMD_CONNECT, 0, ATYP_IPV6], &addr.octets(), &[0, 22]]);
+        let target = accept_connect(&mut stream).unwrap();
+
+        assert_eq!(target, ("2001:db8::1".to_string(), 22));
+    }
+
+    #[test]
+    fn accept_rejects_other_protocols() {
+        let mut stream = script(&[&[4, 1, 0, 22]]);
+        let e = accept_connect(&mut stream).unwrap_err();
+
+        assert_eq!(e.kind(), ErrorKind::InvalidData);
+        assert!(stream.output.is_empty());
+    }
+
+    #[test]
+    fn accept_rejects_clients_requiring_auth() {
+        let mut stream = script(&[&[5, 1, METHOD_USERNAME_PASSWORD]]);
+        let e = accept_connect(&mut stream).unwrap_err();
+
+        assert_eq!(e.kind(), ErrorKind::PermissionDenied);
+        assert_eq!(stream.output, [5, METHOD_NONE_ACCEPTABLE]);
+    }
+
+    #[test]
+    fn accept_rejects_commands_other_than_connect() {
+        // BIND
+        let mut stream = script(&[&[5, 1, METHOD_NO_AUTH, 5, 0x02, 0, ATYP_IPV4]]);
+        let e = accept_connect(&mut stream).unwrap_err();
+
+        assert_eq!(e.kind(), ErrorKind::Unsupported);
+        assert_eq!(stream.output[2..4], [5, Reply::CommandNotSupported as u8]);
+    }
+
+    #[test]
+    fn accept_rejects_unknown_address_type() {
+        let mut stream = script(&[&[5, 1, METHOD_NO_AUTH, 5, CMD_CONNECT, 0, 0x09]]);
+        let e = accept_connect(&mut stream).unwrap_err();
+
+        assert_eq!(e.kind(), ErrorKind::Unsupported);
+        assert_eq!(stream.output[2..4], [5, Reply::AddressTypeNotSupported as u8]);
+    }
+
+    #[test]
+    fn accept_rejects_invalid_domain_name() {
+        let mut stream = script(&[&[5, 1, METHOD_NO_AUTH, 5, CMD_CONNECT, 0, ATYP_DOMAIN, 2, 0xff, 0xfe, 0, 22]]);
+        let e = accept_connect(&mut stream).unwrap_err();
+
+        assert_eq!(e.kind(), ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn accept_fails_on_truncated_request() {
+        let mut stream = script(&[&[5, 1, METHOD_NO_AUTH, 5, CMD_CONNECT, 0, ATYP_IPV4, 10, 0]]);
+        let e = accept_connect(&mut stream).unwrap_err();
+
+        assert_eq!(e.kind(), ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn send_reply_reports_unspecified_bound_address() {
+        let mut stream = Scripted::new(&[]);
+        send_reply(&mut stream, Reply::HostUnreachable).unwrap();
+
+        assert_eq!(stream.output, [5, Reply::HostUnreachable as u8, 0, ATYP_IPV4, 0, 0, 0, 0, 0, 0]);
+    }
+}
@@ -1,165 +1,1329 @@
-use ssh2::Session;
+use ssh2::{ErrorCode, KeyboardInteractivePrompt, Prompt, Session, Sftp};
 use std::io::{Read, Write};
-use std::net::TcpStream;
-use std::path::Path;
-use std::sync::Arc;
-use tokio::sync::Mutex;
-use std::collections::HashMap;
-use crate::session::ConnectionConfig;
-use log::info;
+use std::net::{Shutdown, TcpStream, ToSocketAddrs};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, RwLock, Weak};
+use std::time::{Duration, Instant};
+use std::collections::{HashMap, HashSet};
+use serde::Serialize;
+use tauri::{AppHandle, Emitter};
+use crate::agent;
+use crate::algorithms::NegotiatedMethods;
+use crate::channel::{RawChannel, ShellChannel};
+use crate::charset::{self, OutputDecoder};
+use crate::session::{ConnectionConfig, JumpHost};
+use crate::tunnel;
+use crate::config::{KeepaliveConfig, ProxyConfig, ReconnectConfig, SecurityConfig, TimeoutConfig};
+use crate::crypto::SecureStorage;
+use crate::forward::{self, Forward, ForwardInfo};
+use crate::inbound::{self, Acceptor, InboundKind, Peer};
+use crate::keys::{self, PassphraseCache};
+use crate::known_hosts::{HostKeyStatus, KnownHostsStore};
+use crate::exec::{self, ExecResult};
+use crate::prompt::PromptBroker;
+use crate::proxy;
+use crate::sftp::{self, SftpEntry};
+use crate::transfer::{TransferDirection, TransferInfo, TransferQueue};
+use crate::x11::{self, X11Display};
+use log::{info, warn};
 
+/// Upper bound on how much output is batched into a single event.
+const MAX_OUTPUT_CHUNK: usize = 64 * 1024;
+/// Reader poll interval bounds; the interval backs off while a channel is idle.
+const IDLE_POLL_MIN: Duration = Duration::from_millis(2);
+const IDLE_POLL_MAX: Duration = Duration::from_millis(20);
+/// Wrong passphrases tolerated before key authentication gives up.
+const MAX_PASSPHRASE_ATTEMPTS: u32 = 3;
+/// Terminal size for a new shell until the UI sends the real one.
+const DEFAULT_PTY_SIZE: (u32, u32) = (80, 24);
+/// Environment variable named in liveness probes. Servers refuse it once the
+/// shell is running; the refusal is the reply we are after.
+const KEEPALIVE_PROBE_VAR: &str = "SSH_TERMINAL_KEEPALIVE";
+/// libssh2 error codes (`libssh2.h`) for a blocking call that ran past the
+/// session timeout, a channel request the server answered with a failure,
+/// and a call that would have blocked.
+const LIBSSH2_ERROR_TIMEOUT: i32 = -9;
+const LIBSSH2_ERROR_CHANNEL_REQUEST_DENIED: i32 = -22;
+const LIBSSH2_ERROR_EAGAIN: i32 = -37;
+/// libssh2's errors about one channel rather than the whole session, from
+/// `LIBSSH2_ERROR_CHANNEL_OUTOFORDER` to `LIBSSH2_ERROR_CHANNEL_EOF_SENT`.
+const LIBSSH2_CHANNEL_ERRORS: std::ops::RangeInclusive<i32> = -27..=-20;
+
+#[derive(Clone, Serialize)]
+struct OutputEvent {
+    session_id: String,
+    data: String,
+}
+
+/// Sent as `ssh-closed` when a shell ends, whether it exited, was
+/// disconnected or failed.
+#[derive(Clone, Serialize)]
+struct ClosedEvent {
+    session_id: String,
+    /// Why the session failed, if the shell didn't end on the remote side.
+    error: Option<String>,
+    exit_status: Option<i32>,
+    /// Signal name without the `SIG` prefix, e.g. `"KILL"`.
+    exit_signal: Option<String>,
+    /// The server's message accompanying `exit_signal`, if it sent one.
+    signal_message: Option<String>,
+    /// Whether the shell ended without an error and wasn't killed by a signal.
+    clean: bool,
+}
+
+impl ClosedEvent {
+    fn new(session_id: &str, ended: &Result<Option<ShellExit>, String>) -> Self {
+        let (error, exit) = match ended {
+            Ok(exit) => (None, exit.clone().unwrap_or_default()),
+            Err(e) => (Some(e.clone()), ShellExit::default()),
+        };
+        Self {
+            session_id: session_id.to_string(),
+            clean: error.is_none() && exit.exit_signal.is_none(),
+            error,
+            exit_status: exit.exit_status,
+            exit_signal: exit.exit_signal,
+            signal_message: exit.signal_message,
+        }
+    }
+}
+
+#[derive(Clone, Serialize)]
+struct ConnectionLostEvent {
+    session_id: String,
+    reason: String,
+}
+
+#[derive(Debug, Clone, Copy, Serialize)]
+#[serde(rename_all = "snake_case")]
+enum ReconnectState {
+    Reconnecting,
+    Attempt,
+    Restored,
+    GaveUp,
+}
+
+#[derive(Clone, Serialize)]
+struct ReconnectEvent {
+    /// The dropped session's ID, which the UI still knows the tab by.
+    session_id: String,
+    state: ReconnectState,
+    attempt: u32,
+    max_attempts: u32,
+    new_session_id: Option<String>,
+    error: Option<String>,
+}
+
+#[derive(Clone, Serialize)]
+struct BroadcastEvent {
+    session_ids: Vec<String>,
+}
+
+/// How the remote side ended a shell, as reported before it closed the channel.
+#[derive(Debug, Clone, Default)]
+struct ShellExit {
+    /// Unset when the shell was killed by a signal.
+    exit_status: Option<i32>,
+    exit_signal: Option<String>,
+    signal_message: Option<String>,
+}
+
+impl ShellExit {
+    /// Finishes closing a channel the server has sent EOF on and reads its
+    /// exit status. Must be called in blocking mode.
+    fn collect(channel: &mut dyn ShellChannel) -> Self {
+        channel.close().ok();
+        channel.wait_close().ok();
+        
+        let signal = channel.exit_signal().ok();
+        let exit_signal = signal.as_ref().and_then(|s| s.exit_signal.clone());
+        let signal_message = signal
+            .and_then(|s| s.error_message)
+            .filter(|message| !message.is_empty());
+        let exit_status = match exit_signal {
+            Some(_) => None,
+            None => channel.exit_status().ok(),
+        };
+        Self {
+            exit_status,
+            exit_signal,
+            signal_message,
+        }
+    }
+}
+
+enum ReadOutcome {
+    Data(String),
+    Idle,
+    /// The shell ended, with what was left in its decoder and its exit
+    /// details if the server sent EOF.
+    Closed(String, Option<ShellExit>),
+}
+
+/// Everything needed to open a session, kept so a dropped one can be
+/// reopened with the same settings and credentials.
+#[derive(Clone)]
+pub struct ConnectParams {
+    pub config: ConnectionConfig,
+    pub jump_hosts: Vec<JumpHost>,
+    pub security: SecurityConfig,
+    pub keepalive: KeepaliveConfig,
+    pub reconnect: ReconnectConfig,
+    pub timeouts: TimeoutConfig,
+    /// The proxy to reach the first host through, already resolved from the
+    /// session's and the app's settings.
+    pub proxy: Option<ProxyConfig>,
+}
+
+/// A connect in progress, registered under the pending ID the UI chose so it
+/// can be aborted before a session ID exists.
+#[derive(Default)]
+pub struct PendingConnect {
+    cancelled: AtomicBool,
+    /// Sockets opened so far; shutting them down fails whatever libssh2 call
+    /// is blocked on them.
+    sockets: Mutex<Vec<TcpStream>>,
+    notify: tokio::sync::Notify,
+}
+
+impl PendingConnect {
+    fn check(&self) -> Result<(), SshError> {
+        if self.cancelled.load(Ordering::SeqCst) {
+            Err(SshError::ConnectCancelled)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Shuts `tcp` down if the connect is cancelled later; fails if it
+    /// already has been.
+    fn watch(&self, tcp: &TcpStream) -> Result<(), SshError> {
+        if let Ok(tcp) = tcp.try_clone() {
+            self.sockets.lock().unwrap().push(tcp);
+        }
+        self.check()
+    }
+
+    fn cancel(&self) {
+        self.cancelled.store(true, Ordering::SeqCst);
+        for tcp in self.sockets.lock().unwrap().drain(..) {
+            tcp.shutdown(Shutdown::Both).ok();
+        }
+        self.notify.notify_one();
+    }
+}
+
+/// An interactive shell channel, one per terminal tab.
+struct Shell {
+    channel: Box<dyn ShellChannel>,
+    /// Last size the UI asked for, as (cols, rows).
+    pty_size: (u32, u32),
+    /// The remote charset, which input is encoded to.
+    encoding: &'static encoding_rs::Encoding,
+    /// Holds back partial characters between reads.
+    decoder: OutputDecoder,
+}
+
+/// An authenticated SSH session and the channels open on it.
+///
+/// Every shell is registered with `SshManager` under its own channel ID, all
+/// pointing at the same connection, so a second tab to a host doesn't need
+/// another login. Exec channels, forwards and transfers are opened on the
+/// same session and counted in `channels` under their own IDs. The session
+/// is closed once the last shell or channel of any kind is gone.
 pub struct SshConnection {
     session: Session,
-    channel: ssh2::Channel,
     stream: TcpStream,
+    shells: HashMap<String, Shell>,
+    /// Exec channels, forwards and transfers using the session.
+    channels: HashSet<String>,
+    /// A bare session channel liveness probes use while no shell is open.
+    probe: Option<RawChannel>,
+    sftp: Option<Sftp>,
+    /// Set once keepalives have declared the connection dead, or once it has
+    /// been closed.
+    lost: Option<String>,
+    params: ConnectParams,
+    /// Accepts forwarded agent and X11 channels until `register` hands it to
+    /// the connection's inbound channel thread.
+    inbound: Option<Acceptor>,
+    /// The local display, when X11 forwarding is on and one was found.
+    x11: Option<Arc<X11Display>>,
 }
 
-pub struct SshManager {
-    connections: HashMap<String, Arc<Mutex<SshConnection>>>,
-}
+impl SshConnection {
+    pub fn session(&self) -> Session {
+        self.session.clone()
+    }
 
-impl SshManager {
-    pub fn new() -> Self {
-        Self {
-            connections: HashMap::new(),
+    /// The session's SFTP subsystem, started on first use.
+    pub fn sftp(&mut self) -> Result<&Sftp, SshError> {
+        if self.sftp.is_none() {
+            let sftp = self.session.sftp()
+                .map_err(|e| SshError::SftpFailed(format!("Failed to start SFTP: {}", e)))?;
+            self.sftp = Some(sftp);
         }
+        Ok(self.sftp.as_ref().unwrap())
     }
 
-    pub async fn connect(&mut self, config: ConnectionConfig) -> Result<String, SshError> {
-        info!("Starting SSH connection to {}:{} as {}", config.host, config.port, config.username);
+    /// Fails fast on a connection known to be dead, where a blocking call
+    /// would otherwise hang until the OS gives up on the socket.
+    fn ensure_alive(&self) -> Result<(), SshError> {
+        match &self.lost {
+            Some(reason) => Err(SshError::ConnectionLost(reason.clone())),
+            None => Ok(()),
+        }
+    }
+
+    /// Keeps the session open for a channel other than a shell until
+    /// `remove_channel` is called with the same ID. Returns false if the ID
+    /// was already added.
+    pub fn add_channel(&mut self, id: &str) -> bool {
+        self.channels.insert(id.to_string())
+    }
+
+    /// Lets go of a channel added with `add_channel`, closing the session if
+    /// nothing else uses it.
+    pub fn remove_channel(&mut self, id: &str) {
+        self.channels.remove(id);
+        self.close_if_unused();
+    }
+
+    /// Closes the session once no shell or other channel is left on it.
+    fn close_if_unused(&mut self) {
+        if !self.shells.is_empty() || !self.channels.is_empty() {
+            return;
+        }
+        self.sftp = None;
+        self.probe = None;
+        // A dead server would never answer the disconnect
+        if self.lost.is_none() {
+            info!("Last channel closed, closing connection");
+            self.session.disconnect(None, "Closed by user", None).ok();
+            self.lost = Some("Connection closed".to_string());
+        }
+    }
+
+    fn shell(&mut self, channel_id: &str) -> Result<&mut Shell, SshError> {
+        self.shells.get_mut(channel_id).ok_or(SshError::SessionNotFound)
+    }
+
+    /// Starts another shell on this session.
+    fn open_shell(&mut self, pty_size: (u32, u32)) -> Result<Shell, SshError> {
+        self.ensure_alive()?;
+        let encoding = charset::lookup(self.params.config.charset.as_deref())?;
         
-        let session_id = uuid::Uuid::new_v4().to_string();
+        info!("Creating channel session...");
+        let mut channel: Box<dyn ShellChannel> = match &self.x11 {
+            // ssh2 has no x11-req, so these shells are driven through libssh2
+            Some(display) => {
+                let mut channel = RawChannel::open_session(&self.session)
+                    .map_err(|e| SshError::ChannelFailed(e.to_string()))?;
+                info!("Requesting X11 forwarding...");
+                let cookie = display.fake_cookie_hex();
+                if let Err(e) = channel.request_x11(x11::AUTH_PROTOCOL, &cookie, display.screen()) {
+                    warn!("X11 forwarding refused: {}", e);
+                }
+                Box::new(channel)
+            }
+            None => Box::new(self.session.channel_session()
+                .map_err(|e| SshError::ChannelFailed(e.to_string()))?),
+        };
         
-        let addr = format!("{}:{}", config.host, config.port);
-        info!("Connecting to address: {}", addr);
+        info!("Requesting PTY...");
+        channel.request_pty("xterm-256color", pty_size)
+            .map_err(|e| SshError::PtyRequestFailed(e.to_string()))?;
         
-        // Use blocking task for SSH operations
-        let result = tokio::task::spawn_blocking(move || {
-            info!("In blocking task - creating TCP connection...");
-            let tcp = TcpStream::connect(&addr)
-                .map_err(|e| SshError::ConnectionFailed(format!("TCP connect failed: {}", e)))?;
-            
-            info!("TCP connection established");
-            
-            info!("Creating SSH session...");
-            let mut session = Session::new()
-                .map_err(|e| SshError::SessionCreationFailed(e.to_string()))?;
-            
-            info!("Setting TCP stream...");
-            session.set_tcp_stream(tcp.try_clone().map_err(|e| SshError::CloneFailed(e.to_string()))?);
-            
-            info!("Starting SSH handshake...");
-            session.handshake()
-                .map_err(|e| SshError::HandshakeFailed(e.to_string()))?;
-            
-            info!("Handshake complete, authenticating...");
-            match config.auth_type.as_str() {
-                "password" => {
-                    info!("Using password authentication");
-                    session.userauth_password(&config.username, &config.auth_value)
-                        .map_err(|e| SshError::AuthFailed(e.to_string()))?;
-                }
-                "key" => {
-                    info!("Using key authentication");
-                    let key_path = Path::new(&config.auth_value);
-                    session.userauth_pubkey_file(&config.username, None, key_path, None)
-                        .map_err(|e| SshError::AuthFailed(format!("Key auth failed: {}", e)))?;
-                }
-                "agent" => {
-                    info!("Using agent authentication");
-                    session.userauth_agent(&config.username)
-                        .map_err(|e| SshError::AuthFailed(format!("Agent auth failed: {}", e)))?;
-                }
-                _ => return Err(SshError::InvalidAuthType),
+        if self.params.config.forward_agent.is_some() {
+            info!("Requesting agent forwarding...");
+            // Servers may refuse it by policy; the shell works without it
+            if let Err(e) = channel.request_auth_agent_forwarding() {
+                warn!("Agent forwarding refused: {}", e);
             }
-            
-            if !session.authenticated() {
-                return Err(SshError::AuthFailed("Authentication failed".to_string()));
+        }
+        
+        info!("Starting shell...");
+        channel.shell()
+            .map_err(|e| SshError::ShellFailed(e.to_string()))?;
+        
+        Ok(Shell {
+            channel,
+            pty_size,
+            encoding,
+            decoder: OutputDecoder::new(encoding),
+        })
+    }
+
+    fn write_input(&mut self, channel_id: &str, text: &str) -> Result<(), SshError> {
+        self.ensure_alive()?;
+        let shell = self.shell(channel_id)?;
+        let data = charset::encode(shell.encoding, text);
+        let channel = &mut shell.channel;
+        channel.write_all(&data)
+            .map_err(|e| SshError::WriteFailed(e.to_string()))?;
+        channel.flush()
+            .map_err(|e| SshError::WriteFailed(e.to_string()))
+    }
+
+    /// Sends a keepalive and checks for a reply to the previous probe.
+    ///
+    /// libssh2 doesn't report replies to its own keepalives, so liveness is
+    /// probed with a channel request the server has to answer, on the first
+    /// shell that is still open. A probe still waiting for its reply is
+    /// picked up again on the next call. A shell that fails the probe is
+    /// skipped for the next one; its own reader reports it. With no shell
+    /// left, as when only forwards or transfers use the connection, a session
+    /// channel is opened for the probes instead. Returns whether a reply came
+    /// in; errors mean the connection is gone.
+    fn check_alive(&mut self) -> Result<bool, String> {
+        self.session.set_blocking(false);
+        
+        let result = (|| {
+            match self.session.keepalive_send() {
+                Err(e) if e.code() != ErrorCode::Session(LIBSSH2_ERROR_EAGAIN) => {
+                    return Err(format!("Keepalive failed: {}", e));
+                }
+                _ => {}
+            }
+            // The map only changes when a shell opens or closes, so a pending
+            // probe is found on the same shell next time
+            for (id, shell) in self.shells.iter_mut() {
+                if shell.channel.eof() {
+                    continue;
+                }
+                match shell.channel.setenv(KEEPALIVE_PROBE_VAR, "1") {
+                    Ok(()) => return Ok(true),
+                    Err(e) if e.code() == ErrorCode::Session(LIBSSH2_ERROR_CHANNEL_REQUEST_DENIED) => return Ok(true),
+                    Err(e) if e.code() == ErrorCode::Session(LIBSSH2_ERROR_EAGAIN) => return Ok(false),
+                    Err(e) if is_channel_error(&e) => warn!("Keepalive probe failed on shell {}: {}", id, e),
+                    Err(e) => return Err(format!("Keepalive probe failed: {}", e)),
+                }
             }
             
-            info!("Authenticated successfully");
-            
-            info!("Creating channel session...");
-            let mut channel = session.channel_session()
-                .map_err(|e| SshError::ChannelFailed(e.to_string()))?;
-            
-            info!("Requesting PTY...");
-            channel.request_pty("xterm-256color", None, Some((80, 24, 0, 0)))
-                .map_err(|e| SshError::PtyRequestFailed(e.to_string()))?;
+            let Some(probe) = &mut self.probe else {
+                // The server has to answer the open as well
+                return match RawChannel::open_session(&self.session) {
+                    Ok(channel) => {
+                        self.probe = Some(channel);
+                        Ok(true)
+                    }
+                    Err(e) if e.code() == ErrorCode::Session(LIBSSH2_ERROR_EAGAIN) => Ok(false),
+                    Err(e) => Err(format!("Keepalive probe failed: {}", e)),
+                };
+            };
+            match probe.setenv(KEEPALIVE_PROBE_VAR, "1") {
+                Ok(()) => Ok(true),
+                Err(e) if e.code() == ErrorCode::Session(LIBSSH2_ERROR_CHANNEL_REQUEST_DENIED) => Ok(true),
+                Err(e) if e.code() == ErrorCode::Session(LIBSSH2_ERROR_EAGAIN) => Ok(false),
+                Err(e) if is_channel_error(&e) => {
+                    // Reopened next time
+                    warn!("Keepalive probe channel failed: {}", e);
+                    self.probe = None;
+                    Ok(false)
+                }
+                Err(e) => Err(format!("Keepalive probe failed: {}", e)),
+            }
+        })();
+        
+        self.session.set_blocking(true);
+        result
+    }
+
+    /// Drains whatever a shell has buffered without blocking, decoded from
+    /// its charset.
+    fn read_available(&mut self, channel_id: &str) -> Result<ReadOutcome, SshError> {
+        self.ensure_alive()?;
+        let Some(shell) = self.shells.get_mut(channel_id) else {
+            return Ok(ReadOutcome::Closed(String::new(), None));
+        };
+        self.session.set_blocking(false);
+        
+        let mut buffer = [0u8; 8192];
+        let mut output = Vec::new();
+        let mut outcome = loop {
+            match shell.channel.read(&mut buffer) {
+                Ok(0) if output.is_empty() => break Ok(ReadOutcome::Closed(shell.decoder.finish(), None)),
+                Ok(0) => break Ok(ReadOutcome::Data(shell.decoder.decode(&output))),
+                Ok(n) => {
+                    output.extend_from_slice(&buffer[..n]);
+                    if output.len() >= MAX_OUTPUT_CHUNK {
+                        break Ok(ReadOutcome::Data(shell.decoder.decode(&output)));
+                    }
+                }
+                Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => {
+                    if output.is_empty() {
+                        break Ok(ReadOutcome::Idle);
+                    }
+                    break Ok(ReadOutcome::Data(shell.decoder.decode(&output)));
+                }
+                Err(e) => break Err(SshError::ReadFailed(e.to_string())),
+            }
+        };
+        
+        self.session.set_blocking(true);
+        if let Ok(ReadOutcome::Closed(_, exit)) = &mut outcome {
+            *exit = Some(ShellExit::collect(shell.channel.as_mut()));
+        }
+        outcome
+    }
+}
+
+/// Streams a shell's output to the webview as `ssh-output` events and calls
+/// `on_closed` once the channel ends or is disconnected: with how the shell
+/// exited, if the server ended it, or with the error if it failed.
+fn spawn_reader<F>(app: AppHandle, session_id: String, connection: Weak<Mutex<SshConnection>>, on_closed: F)
+where
+    F: FnOnce(Result<Option<ShellExit>, String>) + Send + 'static,
+{
+    let thread_name = format!("ssh-reader-{}", &session_id[..8]);
+    let result = std::thread::Builder::new().name(thread_name).spawn(move || {
+        let emit_output = |data: String| {
+            let event = OutputEvent {
+                session_id: session_id.clone(),
+                data,
+            };
+            if let Err(e) = app.emit("ssh-output", event) {
+                warn!("Failed to emit output for {}: {}", session_id, e);
+            }
+        };
+        let mut idle_poll = IDLE_POLL_MIN;
+        let ended = loop {
+            let Some(conn) = connection.upgrade() else {
+                break Ok(None);
+            };
+            let outcome = conn.lock().unwrap().read_available(&session_id);
+            drop(conn);
             
-            info!("Starting shell...");
-            channel.shell()
-                .map_err(|e| SshError::ShellFailed(e.to_string()))?;
+            match outcome {
+                // Only the start of a character arrived; the rest comes with the next read
+                Ok(ReadOutcome::Data(data)) if data.is_empty() => idle_poll = IDLE_POLL_MIN,
+                Ok(ReadOutcome::Data(data)) => {
+                    idle_poll = IDLE_POLL_MIN;
+                    emit_output(data);
+                }
+                Ok(ReadOutcome::Idle) => {
+                    std::thread::sleep(idle_poll);
+                    idle_poll = (idle_poll * 2).min(IDLE_POLL_MAX);
+                }
+                Ok(ReadOutcome::Closed(rest, exit)) => {
+                    // A character cut off by the end of the stream
+                    if !rest.is_empty() {
+                        emit_output(rest);
+                    }
+                    break Ok(exit);
+                }
+                Err(e) => break Err(e.to_string()),
+            }
+        };
+        
+        info!("Output stream for {} ended: {:?}", session_id, ended);
+        on_closed(ended);
+    });
+    
+    if let Err(e) = result {
+        warn!("Failed to spawn reader thread: {}", e);
+    }
+}
+
+/// Sends keepalives on a connection every `config.interval_secs` and declares
+/// it lost after `config.max_missed` intervals without a reply. Each shell on
+/// a lost connection gets an `ssh-connection-lost` event; their readers then
+/// end with the same reason. `label` names the thread and log lines.
+fn spawn_keepalive(
+    app: AppHandle,
+    label: String,
+    connection: Weak<Mutex<SshConnection>>,
+    config: KeepaliveConfig,
+) {
+    if config.interval_secs == 0 {
+        return;
+    }
+    let interval = Duration::from_secs(config.interval_secs as u64);
+    
+    let thread_name = format!("ssh-keepalive-{}", &label[..8]);
+    let result = std::thread::Builder::new().name(thread_name).spawn(move || {
+        let mut missed = 0;
+        loop {
+            std::thread::sleep(interval);
+            let Some(conn) = connection.upgrade() else {
+                break;
+            };
+            let mut conn = conn.lock().unwrap();
+            if conn.lost.is_some() {
+                break;
+            }
             
-            info!("Connection established successfully");
+            let reason = match conn.check_alive() {
+                Ok(true) => {
+                    missed = 0;
+                    None
+                }
+                Ok(false) => {
+                    missed += 1;
+                    (missed >= config.max_missed.max(1))
+                        .then(|| format!("No reply from server for {}s", missed as u64 * interval.as_secs()))
+                }
+                Err(reason) => Some(reason),
+            };
             
-            Ok((session, channel, tcp))
-        }).await;
+            if let Some(reason) = reason {
+                warn!("Connection {} lost: {}", label, reason);
+                conn.lost = Some(reason.clone());
+                let shell_ids: Vec<String> = conn.shells.keys().cloned().collect();
+                drop(conn);
+                for session_id in shell_ids {
+                    let event = ConnectionLostEvent { session_id, reason: reason.clone() };
+                    app.emit("ssh-connection-lost", event).ok();
+                }
+                break;
+            }
+        }
+    });
+    
+    if let Err(e) = result {
+        warn!("Failed to spawn keepalive thread: {}", e);
+    }
+}
+
+/// Owns all live connections.
+///
+/// The connection map is only locked long enough to look up, insert or remove
+/// an entry. Each `SshConnection` has its own lock and is driven from blocking
+/// tasks, so a slow connect or a stalled channel never holds up other sessions.
+pub struct SshManager {
+    app: AppHandle,
+    connections: RwLock<HashMap<String, Arc<Mutex<SshConnection>>>>,
+    known_hosts: Arc<KnownHostsStore>,
+    prompts: Arc<PromptBroker>,
+    passphrases: Arc<PassphraseCache>,
+    secure_storage: Arc<tokio::sync::Mutex<SecureStorage>>,
+    forwards: Mutex<HashMap<String, Forward>>,
+    transfers: Arc<TransferQueue>,
+    /// Sessions whose terminal input is mirrored to each other.
+    broadcast: RwLock<HashSet<String>>,
+    /// Dropped shells currently being reconnected, by their old ID.
+    reconnecting: Mutex<HashSet<String>>,
+    /// Connects still in progress, by pending ID.
+    pending: Mutex<HashMap<String, Arc<PendingConnect>>>,
+}
+
+impl SshManager {
+    pub fn new(
+        app: AppHandle,
+        prompts: Arc<PromptBroker>,
+        secure_storage: Arc<tokio::sync::Mutex<SecureStorage>>,
+        transfer_limit: usize,
+    ) -> Self {
+        Self {
+            transfers: Arc::new(TransferQueue::new(app.clone(), transfer_limit)),
+            app,
+            connections: RwLock::new(HashMap::new()),
+            known_hosts: Arc::new(KnownHostsStore::new()),
+            prompts,
+            passphrases: Arc::new(PassphraseCache::new()),
+            secure_storage,
+            forwards: Mutex::new(HashMap::new()),
+            broadcast: RwLock::new(HashSet::new()),
+            reconnecting: Mutex::new(HashSet::new()),
+            pending: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn clear_passphrase_cache(&self) {
+        self.passphrases.clear();
+    }
+
+    pub fn known_hosts(&self) -> &KnownHostsStore {
+        &self.known_hosts
+    }
+
+    pub fn transfers(&self) -> &Arc<TransferQueue> {
+        &self.transfers
+    }
+
+    fn connection(&self, session_id: &str) -> Result<Arc<Mutex<SshConnection>>, SshError> {
+        self.connections
+            .read()
+            .unwrap()
+            .get(session_id)
+            .cloned()
+            .ok_or(SshError::SessionNotFound)
+    }
+
+    /// Runs `f` against a connection on the blocking pool, holding only that
+    /// connection's lock.
+    async fn with_connection<T, F>(&self, session_id: &str, f: F) -> Result<T, SshError>
+    where
+        T: Send + 'static,
+        F: FnOnce(&mut SshConnection) -> Result<T, SshError> + Send + 'static,
+    {
+        let conn = self.connection(session_id)?;
+        tokio::task::spawn_blocking(move || {
+            let mut conn = conn.lock().unwrap();
+            conn.ensure_alive()?;
+            f(&mut conn)
+        })
+            .await
+            .map_err(|e| SshError::ConnectionFailed(format!("Task failed: {}", e)))?
+    }
+
+    fn auth_context(&self, security: SecurityConfig) -> AuthContext {
+        AuthContext {
+            prompts: self.prompts.clone(),
+            passphrases: self.passphrases.clone(),
+            secure_storage: self.secure_storage.clone(),
+            security,
+        }
+    }
+
+    /// Opens a connection with one shell and returns the shell's channel ID.
+    /// Until then the connect can be aborted with `cancel_connect(pending_id)`.
+    pub async fn connect(self: &Arc<Self>, params: ConnectParams, pending_id: &str) -> Result<String, SshError> {
+        let config = &params.config;
+        info!("Starting SSH connection to {}:{} as {}", config.host, config.port, config.username);
+        // Checked up front rather than after logging in
+        charset::lookup(config.charset.as_deref())?;
+        
+        let pending = Arc::new(PendingConnect::default());
+        self.pending.lock().unwrap().insert(pending_id.to_string(), pending.clone());
+        
+        // Use blocking task for SSH operations
+        let manager = self.clone();
+        let attempt = pending.clone();
+        let task = tokio::task::spawn_blocking(move || {
+            let mut connection = manager.open_connection(params, &attempt)?;
+            let shell = connection.open_shell(DEFAULT_PTY_SIZE)?;
+            let channel_id = uuid::Uuid::new_v4().to_string();
+            connection.shells.insert(channel_id.clone(), shell);
+            Ok((channel_id, connection))
+        });
+        
+        // A cancelled connect returns at once; the blocking task winds down on
+        // its own and drops whatever it managed to open
+        let result = tokio::select! {
+            result = task => result,
+            _ = pending.notify.notified() => Ok(Err(SshError::ConnectCancelled)),
+        };
+        self.pending.lock().unwrap().remove(pending_id);
         
         match result {
-            Ok(Ok((session, channel, stream))) => {
-                let connection = SshConnection {
-                    session,
-                    channel,
-                    stream,
-                };
-                
-                self.connections.insert(
-                    session_id.clone(),
-                    Arc::new(Mutex::new(connection))
-                );
-                
-                Ok(session_id)
+            Ok(_) if pending.check().is_err() => {
+                info!("Connect {} cancelled", pending_id);
+                Err(SshError::ConnectCancelled)
+            }
+            Ok(Ok((channel_id, connection))) => {
+                self.register(connection);
+                Ok(channel_id)
             }
             Ok(Err(e)) => Err(e),
             Err(e) => Err(SshError::ConnectionFailed(format!("Task failed: {}", e))),
         }
     }
 
-    pub async fn disconnect(&mut self, session_id: &str) -> Result<(), SshError> {
-        if let Some(conn) = self.connections.remove(session_id) {
-            let mut conn = conn.lock().await;
-            conn.channel.send_eof().ok();
-            conn.channel.wait_eof().ok();
-            conn.channel.close().ok();
-            conn.channel.wait_close().ok();
-        }
+    /// Aborts a connect that hasn't returned yet.
+    pub fn cancel_connect(&self, pending_id: &str) -> Result<(), SshError> {
+        let pending = self.pending.lock().unwrap().get(pending_id).cloned();
+        pending.ok_or(SshError::PendingConnectNotFound)?.cancel();
         Ok(())
     }
 
-    pub async fn send_command(&self, session_id: &str, command: &str) -> Result<(), SshError> {
-        if let Some(conn) = self.connections.get(session_id) {
-            let mut conn = conn.lock().await;
-            conn.channel.write_all(command.as_bytes())
-                .map_err(|e| SshError::WriteFailed(e.to_string()))?;
-            conn.channel.flush()
-                .map_err(|e| SshError::WriteFailed(e.to_string()))?;
-            Ok(())
+    /// Connects and authenticates, without opening any channels. Blocks until done.
+    fn open_connection(&self, params: ConnectParams, pending: &PendingConnect) -> Result<SshConnection, SshError> {
+        let auth = self.auth_context(params.security.clone());
+        let (session, stream) = open_transport(
+            &params.config,
+            &params.jump_hosts,
+            params.proxy.as_ref(),
+            &self.known_hosts,
+            &auth,
+            &params.timeouts,
+            pending,
+        )?;
+        pending.check()?;
+        
+        info!("Connection established successfully");
+        
+        // libssh2 only sends keepalives on sessions with an interval set
+        if params.keepalive.interval_secs > 0 {
+            session.set_keepalive(true, params.keepalive.interval_secs);
+        }
+        
+        let x11 = if params.config.forward_x11 {
+            match X11Display::from_env() {
+                Ok(display) => Some(Arc::new(display)),
+                Err(e) => {
+                    warn!("X11 forwarding unavailable: {}", e);
+                    None
+                }
+            }
         } else {
-            Err(SshError::SessionNotFound)
+            None
+        };
+        
+        // Set up before any shell asks for forwarding
+        let forward_agent = params.config.forward_agent.is_some();
+        let inbound = (forward_agent || x11.is_some()).then(|| {
+            let acceptor = Acceptor::new(&session);
+            if forward_agent {
+                acceptor.accept_agent();
+            }
+            if x11.is_some() {
+                acceptor.accept_x11();
+            }
+            acceptor
+        });
+        
+        Ok(SshConnection {
+            session,
+            stream,
+            shells: HashMap::new(),
+            channels: HashSet::new(),
+            probe: None,
+            sftp: None,
+            lost: None,
+            params,
+            inbound,
+            x11,
+        })
+    }
+
+    /// Opens another shell on the connection `session_id` belongs to, without
+    /// logging in again. Returns the new shell's channel ID.
+    pub async fn open_shell(self: &Arc<Self>, session_id: &str) -> Result<String, SshError> {
+        let conn = self.connection(session_id)?;
+        let channel_id = uuid::Uuid::new_v4().to_string();
+        
+        let connection = conn.clone();
+        let id = channel_id.clone();
+        tokio::task::spawn_blocking(move || {
+            let mut conn = connection.lock().unwrap();
+            let shell = conn.open_shell(DEFAULT_PTY_SIZE)?;
+            conn.shells.insert(id, shell);
+            Ok::<_, SshError>(())
+        })
+        .await
+        .map_err(|e| SshError::ChannelFailed(format!("Task failed: {}", e)))??;
+        
+        self.register_shell(&channel_id, &conn);
+        info!("Opened shell {} alongside {}", channel_id, session_id);
+        Ok(channel_id)
+    }
+
+    /// Makes every shell on a new connection available under its channel ID
+    /// and starts the connection's keepalive and inbound channel threads.
+    fn register(self: &Arc<Self>, mut connection: SshConnection) {
+        let keepalive = connection.params.keepalive.clone();
+        let forward_agent = connection.params.config.forward_agent;
+        let acceptor = connection.inbound.take();
+        let x11 = connection.x11.clone();
+        let shell_ids: Vec<String> = connection.shells.keys().cloned().collect();
+        let connection = Arc::new(Mutex::new(connection));
+        
+        for channel_id in &shell_ids {
+            self.register_shell(channel_id, &connection);
+        }
+        let Some(label) = shell_ids.first() else {
+            return;
+        };
+        spawn_keepalive(self.app.clone(), label.clone(), Arc::downgrade(&connection), keepalive);
+        
+        if let Some(acceptor) = acceptor {
+            let secure_storage = self.secure_storage.clone();
+            let passphrases = self.passphrases.clone();
+            inbound::spawn_service(label, acceptor, Arc::downgrade(&connection), move |kind| match kind {
+                InboundKind::Agent => {
+                    let source = forward_agent.ok_or(std::io::ErrorKind::Unsupported)?;
+                    agent::open(source, &secure_storage, &passphrases)
+                        .map(|peer| Box::new(peer) as Box<dyn Peer>)
+                }
+                InboundKind::X11 { .. } => {
+                    let display = x11.as_ref().ok_or(std::io::ErrorKind::Unsupported)?;
+                    display.open().map(|peer| Box::new(peer) as Box<dyn Peer>)
+                }
+            });
         }
     }
 
-    pub async fn read_output(&self, session_id: &str) -> Result<String, SshError> {
-        if let Some(conn) = self.connections.get(session_id) {
-            let mut conn = conn.lock().await;
-            // Set non-blocking so read doesn't hold the mutex forever
-            conn.session.set_blocking(false);
-            let mut buffer = [0u8; 8192];
-            let mut output = String::new();
+    /// Makes one shell available under `channel_id` and starts its reader.
+    fn register_shell(self: &Arc<Self>, channel_id: &str, connection: &Arc<Mutex<SshConnection>>) {
+        self.connections.write().unwrap().insert(channel_id.to_string(), connection.clone());
+        
+        let manager = Arc::downgrade(self);
+        let id = channel_id.to_string();
+        spawn_reader(self.app.clone(), id.clone(), Arc::downgrade(connection), move |ended| {
+            if let Some(manager) = manager.upgrade() {
+                manager.handle_closed(&id, ended);
+            }
+        });
+    }
+
+    /// Called when a shell's output ends. A connection that dropped with an
+    /// error while still open is reconnected, all its shells together, if its
+    /// policy allows; otherwise the UI gets `ssh-closed`.
+    fn handle_closed(self: &Arc<Self>, session_id: &str, ended: Result<Option<ShellExit>, String>) {
+        if self.reconnecting.lock().unwrap().contains(session_id) {
+            return;
+        }
+        
+        if let (Ok(conn), Err(reason)) = (self.connection(session_id), ended.clone()) {
+            let conn = conn.lock().unwrap();
+            let mut reconnecting = self.reconnecting.lock().unwrap();
+            // Every shell's reader sees the drop; whichever gets here first
+            // claims the others while holding the connection lock
+            if reconnecting.contains(session_id) {
+                return;
+            }
             
-            match conn.channel.read(&mut buffer) {
-                Ok(0) => Ok(output),
-                Ok(n) => {
-                    output.push_str(&String::from_utf8_lossy(&buffer[..n]));
-                    Ok(output)
+            let policy = &conn.params.reconnect;
+            if policy.enabled && policy.max_attempts > 0 {
+                let shells: Vec<(String, (u32, u32))> = conn.shells
+                    .iter()
+                    .map(|(id, shell)| (id.clone(), shell.pty_size))
+                    .collect();
+                reconnecting.extend(shells.iter().map(|(id, _)| id.clone()));
+                drop(reconnecting);
+                let params = conn.params.clone();
+                drop(conn);
+                
+                let manager = self.clone();
+                let spawned = std::thread::Builder::new()
+                    .name(format!("ssh-reconnect-{}", &session_id[..8]))
+                    .spawn(move || manager.reconnect(shells, params, reason));
+                match spawned {
+                    Ok(_) => return,
+                    Err(e) => {
+                        warn!("Failed to spawn reconnect thread: {}", e);
+                        self.reconnecting.lock().unwrap().remove(session_id);
+                    }
                 }
-                Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => Ok(output),
-                Err(e) => Err(SshError::ReadFailed(e.to_string())),
             }
-        } else {
-            Err(SshError::SessionNotFound)
         }
+        
+        self.app.emit("ssh-closed", ClosedEvent::new(session_id, &ended)).ok();
+    }
+
+    /// Reopens a dropped connection with exponential backoff and starts a new
+    /// shell for each old one. Forwards and transfers on the old connection
+    /// are stopped; broadcast membership moves to the new shells. Shells the UI
+    /// disconnects in the meantime are left out, and once none are left the
+    /// reconnect is abandoned.
+    fn reconnect(self: Arc<Self>, shells: Vec<(String, (u32, u32))>, params: ConnectParams, reason: String) {
+        let policy = params.reconnect.clone();
+        let label = shells.first().map(|(id, _)| id.clone()).unwrap_or_default();
+        info!("Connection of {} dropped ({}), reconnecting {} shell(s)", label, reason, shells.len());
+        
+        let mut broadcasting = HashSet::new();
+        let mut dropped = None;
+        for (old_id, _) in &shells {
+            dropped = self.connections.write().unwrap().remove(old_id).or(dropped);
+            if self.broadcast.write().unwrap().remove(old_id) {
+                broadcasting.insert(old_id.clone());
+            }
+        }
+        if let Some(dropped) = dropped {
+            self.stop_connection_forwards(&dropped);
+            self.transfers.cancel_connection(&dropped);
+        }
+        
+        let emit = |old_id: &str, state, attempt, new_session_id: Option<String>, error: Option<String>| {
+            let event = ReconnectEvent {
+                session_id: old_id.to_string(),
+                state,
+                attempt,
+                max_attempts: policy.max_attempts,
+                new_session_id,
+                error,
+            };
+            self.app.emit("ssh-reconnect", event).ok();
+        };
+        // Old shells the UI hasn't disconnected since
+        let remaining = || {
+            let reconnecting = self.reconnecting.lock().unwrap();
+            shells.iter().filter(|(id, _)| reconnecting.contains(id)).cloned().collect::<Vec<_>>()
+        };
+        
+        for (old_id, _) in &shells {
+            emit(old_id, ReconnectState::Reconnecting, 0, None, Some(reason.clone()));
+        }
+        
+        let max_delay = Duration::from_secs(policy.max_delay_secs.max(1) as u64);
+        let mut delay = Duration::from_secs(policy.initial_delay_secs as u64).min(max_delay);
+        let mut last_error = reason.clone();
+        for attempt in 1..=policy.max_attempts {
+            std::thread::sleep(delay);
+            let pending = remaining();
+            if pending.is_empty() {
+                info!("Reconnect of {} cancelled", label);
+                return;
+            }
+            
+            for (old_id, _) in &pending {
+                emit(old_id, ReconnectState::Attempt, attempt, None, None);
+            }
+            match self.reopen_shells(&params, &pending) {
+                Ok((mut connection, reopened)) => {
+                    let restored: Vec<(String, String)> = {
+                        let mut reconnecting = self.reconnecting.lock().unwrap();
+                        reopened
+                            .into_iter()
+                            .filter(|(old_id, new_id)| {
+                                let wanted = reconnecting.remove(old_id);
+                                if !wanted {
+                                    connection.shells.remove(new_id);
+                                }
+                                wanted
+                            })
+                            .collect()
+                    };
+                    if restored.is_empty() {
+                        return;
+                    }
+                    
+                    self.register(connection);
+                    for (old_id, new_id) in restored {
+                        if broadcasting.contains(&old_id) {
+                            self.add_to_broadcast(&new_id).ok();
+                        }
+                        info!("Session {} restored as {}", old_id, new_id);
+                        emit(&old_id, ReconnectState::Restored, attempt, Some(new_id), None);
+                    }
+                    return;
+                }
+                Err(e) => {
+                    warn!("Reconnect attempt {} for {} failed: {}", attempt, label, e);
+                    last_error = e.to_string();
+                }
+            }
+            delay = (delay * 2).max(Duration::from_secs(1)).min(max_delay);
+        }
+        
+        let given_up = remaining();
+        {
+            let mut reconnecting = self.reconnecting.lock().unwrap();
+            for (old_id, _) in &given_up {
+                reconnecting.remove(old_id);
+            }
+        }
+        for (old_id, _) in given_up {
+            emit(&old_id, ReconnectState::GaveUp, policy.max_attempts, None, Some(last_error.clone()));
+            let event = ClosedEvent::new(&old_id, &Err(reason.clone()));
+            self.app.emit("ssh-closed", event).ok();
+        }
+    }
+
+    /// Opens a new connection with `params` and one shell per entry in
+    /// `shells`. Returns it with the (old, new) channel ID pairs.
+    fn reopen_shells(
+        &self,
+        params: &ConnectParams,
+        shells: &[(String, (u32, u32))],
+    ) -> Result<(SshConnection, Vec<(String, String)>), SshError> {
+        let mut connection = self.open_connection(params.clone(), &PendingConnect::default())?;
+        let mut reopened = Vec::new();
+        for (old_id, pty_size) in shells {
+            let shell = connection.open_shell(*pty_size)?;
+            let new_id = uuid::Uuid::new_v4().to_string();
+            connection.shells.insert(new_id.clone(), shell);
+            reopened.push((old_id.clone(), new_id));
+        }
+        Ok((connection, reopened))
+    }
+
+    /// Closes a shell along with the forwards and transfers started from it.
+    /// The connection is closed too once no other shell, exec channel, forward
+    /// or transfer is using it.
+    pub async fn disconnect(&self, session_id: &str) -> Result<(), SshError> {
+        self.reconnecting.lock().unwrap().remove(session_id);
+        self.stop_session_forwards(session_id);
+        self.transfers.cancel_session(session_id);
+        self.remove_from_broadcast(session_id);
+        
+        let conn = self.connections.write().unwrap().remove(session_id);
+        if let Some(conn) = conn {
+            let id = session_id.to_string();
+            tokio::task::spawn_blocking(move || {
+                let mut conn = conn.lock().unwrap();
+                // A dead server would never answer the close handshake
+                let lost = conn.lost.is_some();
+                if let Some(mut shell) = conn.shells.remove(&id) {
+                    if !lost {
+                        shell.channel.send_eof().ok();
+                        shell.channel.wait_eof().ok();
+                        shell.channel.close().ok();
+                        shell.channel.wait_close().ok();
+                    }
+                }
+                conn.close_if_unused();
+            })
+            .await
+            .ok();
+        }
+        Ok(())
+    }
+
+    pub fn start_local_forward(
+        &self,
+        session_id: &str,
+        bind_host: &str,
+        bind_port: u16,
+        target_host: &str,
+        target_port: u16,
+    ) -> Result<ForwardInfo, SshError> {
+        let conn = self.connection(session_id)?;
+        let forward = forward::start_local(session_id, conn, bind_host, bind_port, target_host, target_port)?;
+        Ok(self.add_forward(forward))
+    }
+
+    pub fn start_remote_forward(
+        &self,
+        session_id: &str,
+        bind_host: Option<&str>,
+        bind_port: u16,
+        target_host: &str,
+        target_port: u16,
+    ) -> Result<ForwardInfo, SshError> {
+        let conn = self.connection(session_id)?;
+        let forward = forward::start_remote(session_id, conn, bind_host, bind_port, target_host, target_port)?;
+        Ok(self.add_forward(forward))
+    }
+
+    pub fn start_dynamic_forward(
+        &self,
+        session_id: &str,
+        bind_host: &str,
+        bind_port: u16,
+    ) -> Result<ForwardInfo, SshError> {
+        let conn = self.connection(session_id)?;
+        let forward = forward::start_dynamic(session_id, conn, bind_host, bind_port)?;
+        Ok(self.add_forward(forward))
+    }
+
+    /// Registers a started forward, which keeps its connection open until the
+    /// forward is stopped.
+    fn add_forward(&self, forward: Forward) -> ForwardInfo {
+        let info = forward.info();
+        forward.connection().lock().unwrap().add_channel(&info.id);
+        self.forwards.lock().unwrap().insert(info.id.clone(), forward);
+        info
+    }
+
+    /// Lists forwards, optionally only those of one session.
+    pub fn list_forwards(&self, session_id: Option<&str>) -> Vec<ForwardInfo> {
+        self.forwards
+            .lock()
+            .unwrap()
+            .values()
+            .filter(|f| session_id.map_or(true, |id| f.session_id() == id))
+            .map(Forward::info)
+            .collect()
+    }
+
+    pub fn stop_forward(&self, forward_id: &str) -> Result<(), SshError> {
+        let forward = self.forwards.lock().unwrap().remove(forward_id)
+            .ok_or(SshError::ForwardNotFound)?;
+        end_forward(forward);
+        Ok(())
+    }
+
+    fn stop_session_forwards(&self, session_id: &str) {
+        self.stop_forwards_where(|forward| forward.session_id() == session_id);
+    }
+
+    fn stop_connection_forwards(&self, connection: &Arc<Mutex<SshConnection>>) {
+        self.stop_forwards_where(|forward| Arc::ptr_eq(forward.connection(), connection));
+    }
+
+    fn stop_forwards_where(&self, matches: impl Fn(&Forward) -> bool) {
+        let stopped: Vec<Forward> = {
+            let mut forwards = self.forwards.lock().unwrap();
+            let ids: Vec<String> = forwards
+                .iter()
+                .filter(|(_, forward)| matches(forward))
+                .map(|(id, _)| id.clone())
+                .collect();
+            ids.iter().filter_map(|id| forwards.remove(id)).collect()
+        };
+        for forward in stopped {
+            end_forward(forward);
+        }
+    }
+
+    /// Writes terminal input to a session. If the session is in the
+    /// broadcast group, the same input goes to every other member too.
+    pub async fn send_command(&self, session_id: &str, command: &str) -> Result<(), SshError> {
+        let data = command.to_string();
+        let others: Vec<String> = {
+            let group = self.broadcast.read().unwrap();
+            if group.contains(session_id) {
+                group.iter().filter(|id| *id != session_id).cloned().collect()
+            } else {
+                Vec::new()
+            }
+        };
+        
+        // Written side by side so one stalled member doesn't hold up the rest
+        let mut mirrored = tokio::task::JoinSet::new();
+        for id in others {
+            let Ok(conn) = self.connection(&id) else {
+                continue;
+            };
+            let data = data.clone();
+            mirrored.spawn_blocking(move || {
+                let result = conn.lock().unwrap().write_input(&id, &data);
+                (id, result)
+            });
+        }
+        
+        let id = session_id.to_string();
+        let result = self.with_connection(session_id, move |conn| conn.write_input(&id, &data)).await;
+        
+        while let Some(joined) = mirrored.join_next().await {
+            if let Ok((id, Err(e))) = joined {
+                warn!("Broadcast input to {} failed: {}", id, e);
+            }
+        }
+        result
+    }
+
+    pub fn broadcast_group(&self) -> Vec<String> {
+        let mut ids: Vec<String> = self.broadcast.read().unwrap().iter().cloned().collect();
+        ids.sort();
+        ids
+    }
+
+    /// Replaces the broadcast group. An empty list turns broadcasting off.
+    pub fn set_broadcast_group(&self, session_ids: Vec<String>) -> Result<(), SshError> {
+        for id in &session_ids {
+            self.connection(id)?;
+        }
+        *self.broadcast.write().unwrap() = session_ids.into_iter().collect();
+        self.emit_broadcast_group();
+        Ok(())
+    }
+
+    pub fn add_to_broadcast(&self, session_id: &str) -> Result<(), SshError> {
+        self.connection(session_id)?;
+        if self.broadcast.write().unwrap().insert(session_id.to_string()) {
+            self.emit_broadcast_group();
+        }
+        Ok(())
+    }
+
+    pub fn remove_from_broadcast(&self, session_id: &str) {
+        if self.broadcast.write().unwrap().remove(session_id) {
+            self.emit_broadcast_group();
+        }
+    }
+
+    fn emit_broadcast_group(&self) {
+        let event = BroadcastEvent {
+            session_ids: self.broadcast_group(),
+        };
+        if let Err(e) = self.app.emit("broadcast-changed", event) {
+            warn!("Failed to emit broadcast group: {}", e);
+        }
+    }
+
+    pub async fn sftp_list(&self, session_id: &str, path: &str) -> Result<Vec<SftpEntry>, SshError> {
+        let path = PathBuf::from(path);
+        self.with_connection(session_id, move |conn| sftp::list(conn.sftp()?, &path)).await
+    }
+
+    pub async fn sftp_stat(&self, session_id: &str, path: &str) -> Result<SftpEntry, SshError> {
+        let path = PathBuf::from(path);
+        self.with_connection(session_id, move |conn| sftp::stat(conn.sftp()?, &path)).await
+    }
+
+    pub async fn sftp_mkdir(&self, session_id: &str, path: &str, mode: u32) -> Result<(), SshError> {
+        let path = PathBuf::from(path);
+        self.with_connection(session_id, move |conn| sftp::mkdir(conn.sftp()?, &path, mode)).await
+    }
+
+    pub async fn sftp_rename(&self, session_id: &str, from: &str, to: &str) -> Result<(), SshError> {
+        let (from, to) = (PathBuf::from(from), PathBuf::from(to));
+        self.with_connection(session_id, move |conn| sftp::rename(conn.sftp()?, &from, &to)).await
+    }
+
+    pub async fn sftp_delete(&self, session_id: &str, path: &str) -> Result<(), SshError> {
+        let path = PathBuf::from(path);
+        self.with_connection(session_id, move |conn| sftp::delete(conn.sftp()?, &path)).await
+    }
+
+    pub async fn sftp_chmod(&self, session_id: &str, path: &str, mode: u32) -> Result<(), SshError> {
+        let path = PathBuf::from(path);
+        self.with_connection(session_id, move |conn| sftp::chmod(conn.sftp()?, &path, mode)).await
+    }
+
+    /// Queues an upload or download on the session's transfer queue.
+    pub fn queue_transfer(
+        &self,
+        session_id: &str,
+        direction: TransferDirection,
+        local_path: &str,
+        remote_path: &str,
+        resume: bool,
+    ) -> Result<TransferInfo, SshError> {
+        let conn = self.connection(session_id)?;
+        Ok(self.transfers.enqueue(session_id, conn, direction, local_path, remote_path, resume))
+    }
+
+    /// Re-queues a failed or cancelled transfer, resuming it. Runs on
+    /// `session_id` if given, otherwise on the transfer's original session.
+    pub fn retry_transfer(&self, transfer_id: &str, session_id: Option<&str>) -> Result<TransferInfo, SshError> {
+        let info = self.transfers.get(transfer_id).ok_or(SshError::TransferNotFound)?;
+        let session_id = session_id.unwrap_or(&info.session_id);
+        let conn = self.connection(session_id)?;
+        self.transfers.retry(transfer_id, session_id, conn)
+    }
+
+    /// Runs a single command without a PTY on the session's connection.
+    pub async fn exec(&self, session_id: &str, command: &str, timeout: Duration) -> Result<ExecResult, SshError> {
+        let conn = self.connection(session_id)?;
+        let command = command.to_string();
+        tokio::task::spawn_blocking(move || {
+            let exec_id = uuid::Uuid::new_v4().to_string();
+            let session = {
+                let mut conn = conn.lock().unwrap();
+                conn.add_channel(&exec_id);
+                conn.session()
+            };
+            let result = exec::run(&session, &conn, &command, timeout);
+            conn.lock().unwrap().remove_channel(&exec_id);
+            result
+        })
+        .await
+        .map_err(|e| SshError::ExecFailed(format!("Task failed: {}", e)))?
+    }
+
+    /// Connects with `params` just long enough to run one command, without
+    /// opening a terminal or registering a session. Blocks until done.
+    pub fn exec_detached(
+        &self,
+        params: &ConnectParams,
+        command: &str,
+        timeout: Duration,
+    ) -> Result<ExecResult, SshError> {
+        let auth = self.auth_context(params.security.clone());
+        let pending = PendingConnect::default();
+        let (session, _tcp) = open_transport(
+            &params.config,
+            &params.jump_hosts,
+            params.proxy.as_ref(),
+            &self.known_hosts,
+            &auth,
+            &params.timeouts,
+            &pending,
+        )?;
+        
+        let result = exec::run(&session, &Mutex::new(()), command, timeout);
+        session.disconnect(None, "", None).ok();
+        result
+    }
+
+    /// The algorithms the connection `session_id` belongs to negotiated.
+    pub async fn negotiated_methods(&self, session_id: &str) -> Result<NegotiatedMethods, SshError> {
+        self.with_connection(session_id, |conn| Ok(NegotiatedMethods::of(&conn.session))).await
     }
 
     pub async fn resize_terminal(
@@ -168,13 +1332,482 @@ impl SshManager {
         cols: u32,
         rows: u32,
     ) -> Result<(), SshError> {
-        if let Some(conn) = self.connections.get(session_id) {
-            let mut conn = conn.lock().await;
-            conn.channel.request_pty_size(cols as u32, rows as u32, None, None)
+        let id = session_id.to_string();
+        self.with_connection(session_id, move |conn| {
+            let shell = conn.shell(&id)?;
+            shell.channel.request_pty_size((cols, rows))
                 .map_err(|e| SshError::ResizeFailed(e.to_string()))?;
+            shell.pty_size = (cols, rows);
             Ok(())
-        } else {
-            Err(SshError::SessionNotFound)
+        })
+        .await
+    }
+}
+
+/// Stops a forward and lets go of its connection.
+fn end_forward(forward: Forward) {
+    forward.stop();
+    forward.connection().lock().unwrap().remove_channel(forward.id());
+}
+
+/// Connects to `config`, hopping through `jump_hosts` in order. Each hop's
+/// session tunnels the next hop's TCP stream over a `direct-tcpip` channel.
+/// Returns the authenticated session and the socket it runs over. Only the
+/// first host is dialed, so only it is reached through `proxy`.
+///
+/// Every socket is registered with `pending`, so cancelling it fails the
+/// connect at whichever hop and phase it has reached.
+fn open_transport(
+    config: &ConnectionConfig,
+    jump_hosts: &[JumpHost],
+    proxy: Option<&ProxyConfig>,
+    known_hosts: &KnownHostsStore,
+    auth: &AuthContext,
+    timeouts: &TimeoutConfig,
+    pending: &PendingConnect,
+) -> Result<(Session, TcpStream), SshError> {
+    let mut tunnel: Option<TcpStream> = None;
+    
+    for (index, hop) in jump_hosts.iter().enumerate() {
+        let hop_error = |e: SshError| SshError::JumpHostFailed {
+            hop: format!("{} ({}/{})", hop.name, index + 1, jump_hosts.len()),
+            source: Box::new(e),
+        };
+        
+        info!("Connecting to jump host {} ({}:{})", hop.name, hop.config.host, hop.config.port);
+        let tcp = match tunnel.take() {
+            Some(stream) => stream,
+            None => dial(&hop.config, proxy, timeouts, pending).map_err(hop_error)?,
+        };
+        let session = establish_session(&tcp, &hop.config, known_hosts, auth, timeouts, pending).map_err(hop_error)?;
+        
+        let next = jump_hosts.get(index + 1).map(|h| &h.config).unwrap_or(config);
+        let mut channel = session.channel_direct_tcpip(&next.host, next.port, None)
+            .map_err(|e| hop_error(SshError::ChannelFailed(format!(
+                "direct-tcpip to {}:{} refused: {}", next.host, next.port, e
+            ))))?;
+        
+        let (local, remote) = tunnel::socket_pair()
+            .map_err(|e| hop_error(SshError::ConnectionFailed(format!("Failed to create tunnel socket: {}", e))))?;
+        pending.watch(&local)?;
+        let hop_name = hop.name.clone();
+        std::thread::Builder::new()
+            .name(format!("ssh-jump-{}", hop_name))
+            .spawn(move || {
+                // Keeps the hop's session (and its socket) alive for as long as the tunnel runs
+                let _tcp = tcp;
+                let lock = Mutex::new(());
+                let control = tunnel::RelayControl::default();
+                if let Err(e) = tunnel::relay(&session, &mut channel, remote, &lock, &control) {
+                    warn!("Tunnel through {} ended: {}", hop_name, e);
+                }
+            })
+            .map_err(|e| hop_error(SshError::ConnectionFailed(format!("Failed to start tunnel: {}", e))))?;
+        
+        tunnel = Some(local);
+    }
+    
+    let tcp = match tunnel {
+        Some(stream) => stream,
+        None => dial(config, proxy, timeouts, pending)?,
+    };
+    let session = establish_session(&tcp, config, known_hosts, auth, timeouts, pending)?;
+    Ok((session, tcp))
+}
+
+/// `secs` as a timeout, or `None` for no limit.
+fn timeout_from_secs(secs: u32) -> Option<Duration> {
+    (secs > 0).then(|| Duration::from_secs(secs as u64))
+}
+
+/// Opens the TCP connection to `config`'s host, tunnelled through `proxy` if set.
+fn dial(
+    config: &ConnectionConfig,
+    proxy: Option<&ProxyConfig>,
+    timeouts: &TimeoutConfig,
+    pending: &PendingConnect,
+) -> Result<TcpStream, SshError> {
+    let Some(proxy) = proxy else {
+        return connect_tcp(&config.host, config.port, timeouts, pending);
+    };
+    info!("Connecting through {:?} proxy {}:{}", proxy.kind, proxy.host, proxy.port);
+    let mut tcp = connect_tcp(&proxy.host, proxy.port, timeouts, pending)?;
+    
+    // The proxy's handshake gets the same time limit as the connect
+    let timeout = timeout_from_secs(timeouts.connect_secs);
+    tcp.set_read_timeout(timeout).ok();
+    tcp.set_write_timeout(timeout).ok();
+    let result = proxy::open_tunnel(&mut tcp, proxy, &config.host, config.port);
+    tcp.set_read_timeout(None).ok();
+    tcp.set_write_timeout(None).ok();
+    pending.check()?;
+    
+    result.map_err(|e| match e.kind() {
+        std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut => SshError::TimedOut {
+            phase: "Proxy handshake",
+            secs: timeouts.connect_secs as u64,
+        },
+        _ => SshError::ProxyFailed {
+            proxy: format!("{}:{}", proxy.host, proxy.port),
+            reason: e.to_string(),
+        },
+    })?;
+    info!("Proxy tunnel to {}:{} established", config.host, config.port);
+    Ok(tcp)
+}
+
+/// Connects to the first address `host` resolves to that accepts, giving
+/// up once `timeouts.connect_secs` have passed across all of them.
+fn connect_tcp(host: &str, port: u16, timeouts: &TimeoutConfig, pending: &PendingConnect) -> Result<TcpStream, SshError> {
+    let addr = format!("{}:{}", host, port);
+    info!("Connecting to address: {}", addr);
+    
+    let addresses: Vec<_> = addr.to_socket_addrs()
+        .map_err(|e| SshError::ConnectionFailed(format!("Failed to resolve {}: {}", addr, e)))?
+        .collect();
+    let deadline = timeout_from_secs(timeouts.connect_secs).map(|timeout| Instant::now() + timeout);
+    let timed_out = || SshError::TimedOut { phase: "TCP connect", secs: timeouts.connect_secs as u64 };
+    
+    let mut last_error = None;
+    for address in addresses {
+        pending.check()?;
+        let result = match deadline {
+            Some(deadline) => match deadline.checked_duration_since(Instant::now()) {
+                Some(remaining) if !remaining.is_zero() => TcpStream::connect_timeout(&address, remaining),
+                _ => return Err(timed_out()),
+            },
+            None => TcpStream::connect(address),
+        };
+        match result {
+            Ok(tcp) => {
+                pending.watch(&tcp)?;
+                info!("TCP connection established");
+                return Ok(tcp);
+            }
+            Err(e) => {
+                warn!("TCP connect to {} failed: {}", address, e);
+                last_error = Some(e);
+            }
+        }
+    }
+    
+    match last_error {
+        Some(e) if e.kind() == std::io::ErrorKind::TimedOut => Err(timed_out()),
+        Some(e) => Err(SshError::ConnectionFailed(format!("TCP connect failed: {}", e))),
+        None => Err(SshError::ConnectionFailed(format!("{} did not resolve to any address", addr))),
+    }
+}
+
+fn is_channel_error(e: &ssh2::Error) -> bool {
+    matches!(e.code(), ErrorCode::Session(code) if LIBSSH2_CHANNEL_ERRORS.contains(&code))
+}
+
+/// Whether the last libssh2 call on `session` failed because it ran past the
+/// session timeout.
+fn session_timed_out(session: &Session) -> bool {
+    ssh2::Error::last_session_error(session)
+        .is_some_and(|e| e.code() == ErrorCode::Session(LIBSSH2_ERROR_TIMEOUT))
+}
+
+/// Runs the SSH handshake over `tcp`, verifies the host key and authenticates.
+///
+/// The session timeout bounds the handshake and every authentication call
+/// except keyboard-interactive, whose prompts are answered from inside the
+/// libssh2 call (host key and passphrase prompts happen between calls). It
+/// is lifted before the session is returned.
+fn establish_session(
+    tcp: &TcpStream,
+    config: &ConnectionConfig,
+    known_hosts: &KnownHostsStore,
+    auth: &AuthContext,
+    timeouts: &TimeoutConfig,
+    pending: &PendingConnect,
+) -> Result<Session, SshError> {
+    info!("Creating SSH session...");
+    let mut session = Session::new()
+        .map_err(|e| SshError::SessionCreationFailed(e.to_string()))?;
+    
+    info!("Setting TCP stream...");
+    session.set_tcp_stream(tcp.try_clone().map_err(|e| SshError::CloneFailed(e.to_string()))?);
+    
+    config.algorithms.apply(&session)?;
+    
+    let timed_out = |phase| SshError::TimedOut { phase, secs: timeouts.handshake_secs as u64 };
+    session.set_timeout(timeouts.handshake_secs.saturating_mul(1000));
+    
+    info!("Starting SSH handshake...");
+    session.handshake()
+        .map_err(|e| match e.code() {
+            ErrorCode::Session(LIBSSH2_ERROR_TIMEOUT) => timed_out("SSH handshake"),
+            _ => SshError::HandshakeFailed(e.to_string()),
+        })?;
+    pending.check()?;
+    
+    let methods = NegotiatedMethods::of(&session);
+    info!(
+        "Negotiated kex {:?}, host key {:?}, ciphers {:?}/{:?}, MACs {:?}/{:?}",
+        methods.kex, methods.host_key, methods.cipher_cs, methods.cipher_sc, methods.mac_cs, methods.mac_sc,
+    );
+    
+    if auth.security.verify_host_keys {
+        info!("Verifying host key...");
+        verify_host_key(
+            &session,
+            &config.host,
+            config.port,
+            known_hosts,
+            &auth.prompts,
+            auth.security.strict_host_key_checking,
+        )?;
+    }
+    
+    pending.check()?;
+    
+    info!("Handshake complete, authenticating...");
+    if let Err(e) = authenticate(&session, config, auth) {
+        pending.check()?;
+        return Err(if session_timed_out(&session) { timed_out("Authentication") } else { e });
+    }
+    
+    if !session.authenticated() {
+        return Err(SshError::AuthFailed("Authentication failed".to_string()));
+    }
+    
+    session.set_timeout(0);
+    info!("Authenticated successfully");
+    Ok(session)
+}
+
+/// What authentication needs besides the connection config.
+struct AuthContext {
+    prompts: Arc<PromptBroker>,
+    passphrases: Arc<PassphraseCache>,
+    secure_storage: Arc<tokio::sync::Mutex<SecureStorage>>,
+    security: SecurityConfig,
+}
+
+/// Authenticates `session` using the method named by `config.auth_type`.
+fn authenticate(
+    session: &Session,
+    config: &ConnectionConfig,
+    auth: &AuthContext,
+) -> Result<(), SshError> {
+    match config.auth_type.as_str() {
+        "password" => {
+            info!("Using password authentication");
+            session.userauth_password(&config.username, &config.auth_value)
+                .map_err(|e| SshError::AuthFailed(e.to_string()))?;
+        }
+        "key" => {
+            info!("Using key authentication");
+            let key_path = Path::new(&config.auth_value);
+            let pem = std::fs::read_to_string(key_path)
+                .map_err(|e| SshError::AuthFailed(format!("Failed to read key {}: {}", key_path.display(), e)))?;
+            authenticate_with_key(config, &config.auth_value, &pem, auth, |passphrase| {
+                session.userauth_pubkey_file(&config.username, None, key_path, passphrase)
+            })?;
+        }
+        "stored-key" => {
+            info!("Using stored key authentication");
+            // Decrypted in memory only; the plaintext key never touches disk
+            let pem = auth.secure_storage
+                .blocking_lock()
+                .retrieve(&format!("ssh_key_{}", config.auth_value))
+                .map_err(|e| SshError::AuthFailed(format!("Failed to load stored key '{}': {}", config.auth_value, e)))?;
+            let key_label = keys::stored_key_label(&config.auth_value);
+            authenticate_with_key(config, &key_label, &pem, auth, |passphrase| {
+                session.userauth_pubkey_memory(&config.username, None, &pem, passphrase)
+            })?;
+        }
+        "agent" => {
+            info!("Using agent authentication");
+            session.userauth_agent(&config.username)
+                .map_err(|e| SshError::AuthFailed(format!("Agent auth failed: {}", e)))?;
+        }
+        "keyboard-interactive" => {
+            info!("Using keyboard-interactive authentication");
+            let mut prompter = UiKeyboardInteractive::new(&auth.prompts, &config.host);
+            // libssh2 times the whole exchange, including the user typing
+            // answers, so it runs without the handshake timeout
+            let timeout = session.timeout();
+            session.set_timeout(0);
+            let result = session.userauth_keyboard_interactive(&config.username, &mut prompter);
+            session.set_timeout(timeout);
+            if prompter.cancelled {
+                return Err(SshError::AuthFailed("Keyboard-interactive authentication cancelled".to_string()));
+            }
+            result.map_err(|e| SshError::AuthFailed(format!("Keyboard-interactive auth failed: {}", e)))?;
+        }
+        _ => return Err(SshError::InvalidAuthType),
+    }
+    Ok(())
+}
+
+#[derive(Clone, Serialize)]
+struct PassphraseRequest {
+    host: String,
+    key: String,
+    retry: bool,
+}
+
+/// Runs `try_auth` with whatever passphrase the key needs, asking the UI when
+/// the key is encrypted and the passphrase isn't cached.
+fn authenticate_with_key<F>(
+    config: &ConnectionConfig,
+    key_label: &str,
+    pem: &str,
+    auth: &AuthContext,
+    try_auth: F,
+) -> Result<(), SshError>
+where
+    F: Fn(Option<&str>) -> Result<(), ssh2::Error>,
+{
+    if !keys::is_encrypted(pem) {
+        return try_auth(None)
+            .map_err(|e| SshError::AuthFailed(format!("Key auth failed: {}", e)));
+    }
+    
+    let security = &auth.security;
+    let cache_ttl = security.ssh_key_passphrase_cache
+        .then(|| Duration::from_secs(security.ssh_key_passphrase_cache_ttl as u64));
+    
+    if cache_ttl.is_some() {
+        if let Some(cached) = auth.passphrases.get(key_label) {
+            if try_auth(Some(&cached)).is_ok() {
+                info!("Used cached passphrase for {}", key_label);
+                return Ok(());
+            }
+            auth.passphrases.remove(key_label);
+        }
+    }
+    
+    for attempt in 0..MAX_PASSPHRASE_ATTEMPTS {
+        let request = PassphraseRequest {
+            host: config.host.clone(),
+            key: key_label.to_string(),
+            retry: attempt > 0,
+        };
+        let passphrase = auth.prompts
+            .ask("key-passphrase-prompt", request)
+            .and_then(|reply| reply.as_str().map(str::to_string))
+            .ok_or_else(|| SshError::AuthFailed("Passphrase entry cancelled".to_string()))?;
+        
+        let verified = keys::check_passphrase(pem, &passphrase);
+        if verified == Some(false) {
+            continue;
+        }
+        
+        match try_auth(Some(&passphrase)) {
+            Ok(()) => {
+                if let Some(ttl) = cache_ttl {
+                    auth.passphrases.insert(key_label, &passphrase, ttl);
+                }
+                return Ok(());
+            }
+            // The key decrypted fine, so the server rejected it
+            Err(e) if verified == Some(true) => {
+                return Err(SshError::AuthFailed(format!("Key auth failed: {}", e)));
+            }
+            Err(e) => warn!("Key auth with supplied passphrase failed: {}", e),
+        }
+    }
+    
+    Err(SshError::AuthFailed(format!("Incorrect passphrase for {}", key_label)))
+}
+
+#[derive(Clone, Serialize)]
+struct InteractivePrompt {
+    text: String,
+    echo: bool,
+}
+
+#[derive(Clone, Serialize)]
+struct KeyboardInteractiveRequest {
+    host: String,
+    name: String,
+    instruction: String,
+    prompts: Vec<InteractivePrompt>,
+}
+
+/// Forwards each keyboard-interactive round (e.g. PAM password + OTP) to the UI.
+struct UiKeyboardInteractive<'a> {
+    prompts: &'a PromptBroker,
+    host: String,
+    cancelled: bool,
+}
+
+impl<'a> UiKeyboardInteractive<'a> {
+    fn new(prompts: &'a PromptBroker, host: &str) -> Self {
+        Self {
+            prompts,
+            host: host.to_string(),
+            cancelled: false,
+        }
+    }
+}
+
+impl KeyboardInteractivePrompt for UiKeyboardInteractive<'_> {
+    fn prompt<'b>(&mut self, name: &str, instruction: &str, prompts: &[Prompt<'b>]) -> Vec<String> {
+        // Servers may send informational rounds with no prompts; those need no answer.
+        if prompts.is_empty() || self.cancelled {
+            return vec![String::new(); prompts.len()];
+        }
+        
+        let request = KeyboardInteractiveRequest {
+            host: self.host.clone(),
+            name: name.to_string(),
+            instruction: instruction.to_string(),
+            prompts: prompts
+                .iter()
+                .map(|p| InteractivePrompt { text: p.text.to_string(), echo: p.echo })
+                .collect(),
+        };
+        
+        let answers = self.prompts
+            .ask("keyboard-interactive-prompt", request)
+            .and_then(|reply| serde_json::from_value::<Vec<String>>(reply).ok());
+        
+        match answers {
+            Some(answers) if answers.len() == prompts.len() => answers,
+            _ => {
+                self.cancelled = true;
+                vec![String::new(); prompts.len()]
+            }
+        }
+    }
+}
+
+/// Checks the server's host key against known_hosts. Unknown keys are sent to
+/// the UI for confirmation when `strict` is set and trusted on first use otherwise.
+fn verify_host_key(
+    session: &Session,
+    host: &str,
+    port: u16,
+    known_hosts: &KnownHostsStore,
+    prompts: &PromptBroker,
+    strict: bool,
+) -> Result<(), SshError> {
+    let info = KnownHostsStore::host_key_info(session, host, port)?;
+    
+    match known_hosts.check(session, host, port)? {
+        HostKeyStatus::Trusted => Ok(()),
+        HostKeyStatus::Changed => Err(SshError::HostKeyMismatch {
+            host: format!("{}:{}", host, port),
+            fingerprint: info.fingerprint,
+        }),
+        HostKeyStatus::Unknown => {
+            if strict {
+                let accepted = prompts
+                    .ask("host-key-prompt", info.clone())
+                    .and_then(|reply| reply.as_bool())
+                    .unwrap_or(false);
+                if !accepted {
+                    return Err(SshError::HostKeyRejected(format!("{}:{}", host, port)));
+                }
+            } else {
+                warn!("Trusting new host key for {}:{} ({})", host, port, info.fingerprint);
+            }
+            known_hosts.trust(session, host, port)
         }
     }
 }
@@ -189,6 +1822,18 @@ pub enum SshError {
     CloneFailed(String),
     #[error("Handshake failed: {0}")]
     HandshakeFailed(String),
+    #[error("Host key for {host} does not match known_hosts (server offered {fingerprint}); possible man-in-the-middle attack")]
+    HostKeyMismatch { host: String, fingerprint: String },
+    #[error("Host key for {0} was not accepted")]
+    HostKeyRejected(String),
+    #[error("Host key verification failed: {0}")]
+    HostKeyVerificationFailed(String),
+    #[error("Jump host {hop} failed: {source}")]
+    JumpHostFailed {
+        hop: String,
+        #[source]
+        source: Box<SshError>,
+    },
     #[error("Authentication failed: {0}")]
     AuthFailed(String),
     #[error("Invalid authentication type")]
@@ -203,8 +1848,40 @@ pub enum SshError {
     WriteFailed(String),
     #[error("Read failed: {0}")]
     ReadFailed(String),
+    #[error("Command failed: {0}")]
+    ExecFailed(String),
+    #[error("Command timed out after {0}s")]
+    ExecTimedOut(u64),
     #[error("Resize failed: {0}")]
     ResizeFailed(String),
+    #[error("Port forward failed: {0}")]
+    ForwardFailed(String),
+    #[error("SFTP failed: {0}")]
+    SftpFailed(String),
+    #[error("Transfer cancelled")]
+    TransferCancelled,
+    #[error("Transfer not found")]
+    TransferNotFound,
+    #[error("Port forward not found")]
+    ForwardNotFound,
+    #[error("Connection lost: {0}")]
+    ConnectionLost(String),
+    #[error("{phase} timed out after {secs}s")]
+    TimedOut { phase: &'static str, secs: u64 },
+    #[error("Connection cancelled")]
+    ConnectCancelled,
+    #[error("No connection in progress with that ID")]
+    PendingConnectNotFound,
+    #[error("Proxy {proxy} failed: {reason}")]
+    ProxyFailed { proxy: String, reason: String },
+    #[error("Unknown charset: {0}")]
+    UnknownCharset(String),
+    #[error("No supported {kind} algorithm in \"{prefs}\": {reason}")]
+    UnsupportedAlgorithms {
+        kind: &'static str,
+        prefs: String,
+        reason: String,
+    },
     #[error("Session not found")]
     SessionNotFound,
 }
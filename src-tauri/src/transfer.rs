@@ -0,0 +1,374 @@
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tauri::{AppHandle, Emitter};
+use log::{info, warn};
+
+use crate::sftp;
+use crate::ssh::{SshConnection, SshError};
+
+/// Minimum time between progress events for one transfer.
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(250);
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum TransferDirection {
+    Upload,
+    Download,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum TransferState {
+    Queued,
+    Running,
+    Completed,
+    Failed,
+    Cancelled,
+}
+
+impl TransferState {
+    fn is_finished(self) -> bool {
+        matches!(self, Self::Completed | Self::Failed | Self::Cancelled)
+    }
+}
+
+/// Snapshot of a transfer, sent with every `transfer-progress` event.
+#[derive(Debug, Clone, Serialize)]
+pub struct TransferInfo {
+    pub id: String,
+    pub session_id: String,
+    pub direction: TransferDirection,
+    pub local_path: String,
+    pub remote_path: String,
+    pub state: TransferState,
+    pub bytes_transferred: u64,
+    pub total_bytes: Option<u64>,
+    /// Average rate since this attempt started, not counting resumed bytes.
+    pub bytes_per_second: u64,
+    pub eta_seconds: Option<u64>,
+    pub error: Option<String>,
+}
+
+struct Transfer {
+    info: TransferInfo,
+    connection: Arc<Mutex<SshConnection>>,
+    resume: bool,
+    cancel: Arc<AtomicBool>,
+}
+
+/// Runs SFTP uploads and downloads in the background, at most
+/// `max_concurrent` at a time per connection, however many shells share it,
+/// in the order they were queued.
+/// A transfer keeps its connection open from when it is queued until it
+/// finishes.
+///
+/// Finished transfers stay listed until `clear_finished` so the UI can show
+/// their outcome and offer a retry, which resumes from the partial file.
+pub struct TransferQueue {
+    app: AppHandle,
+    max_concurrent: AtomicUsize,
+    transfers: Mutex<Vec<Transfer>>,
+}
+
+impl TransferQueue {
+    pub fn new(app: AppHandle, max_concurrent: usize) -> Self {
+        Self {
+            app,
+            max_concurrent: AtomicUsize::new(max_concurrent.max(1)),
+            transfers: Mutex::new(Vec::new()),
+        }
+    }
+
+    pub fn set_max_concurrent(self: &Arc<Self>, max_concurrent: usize) {
+        self.max_concurrent.store(max_concurrent.max(1), Ordering::Relaxed);
+        self.pump();
+    }
+
+    pub fn enqueue(
+        self: &Arc<Self>,
+        session_id: &str,
+        connection: Arc<Mutex<SshConnection>>,
+        direction: TransferDirection,
+        local_path: &str,
+        remote_path: &str,
+        resume: bool,
+    ) -> TransferInfo {
+        let info = TransferInfo {
+            id: uuid::Uuid::new_v4().to_string(),
+            session_id: session_id.to_string(),
+            direction,
+            local_path: local_path.to_string(),
+            remote_path: remote_path.to_string(),
+            state: TransferState::Queued,
+            bytes_transferred: 0,
+            total_bytes: None,
+            bytes_per_second: 0,
+            eta_seconds: None,
+            error: None,
+        };
+
+        connection.lock().unwrap().add_channel(&info.id);
+        self.transfers.lock().unwrap().push(Transfer {
+            info: info.clone(),
+            connection,
+            resume,
+            cancel: Arc::new(AtomicBool::new(false)),
+        });
+        self.emit(&info);
+        self.pump();
+        info
+    }
+
+    pub fn get(&self, transfer_id: &str) -> Option<TransferInfo> {
+        self.transfers
+            .lock()
+            .unwrap()
+            .iter()
+            .find(|t| t.info.id == transfer_id)
+            .map(|t| t.info.clone())
+    }
+
+    /// Lists transfers, optionally only those of one session.
+    pub fn list(&self, session_id: Option<&str>) -> Vec<TransferInfo> {
+        self.transfers
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|t| session_id.map_or(true, |id| t.info.session_id == id))
+            .map(|t| t.info.clone())
+            .collect()
+    }
+
+    /// Cancels a queued or running transfer. The partial file is kept so a
+    /// retry can resume it.
+    pub fn cancel(&self, transfer_id: &str) -> Result<(), SshError> {
+        let (info, connection) = {
+            let mut transfers = self.transfers.lock().unwrap();
+            let transfer = transfers
+                .iter_mut()
+                .find(|t| t.info.id == transfer_id)
+                .ok_or(SshError::TransferNotFound)?;
+            transfer.cancel.store(true, Ordering::Relaxed);
+
+            // Running transfers notice the flag at their next chunk
+            if transfer.info.state != TransferState::Queued {
+                return Ok(());
+            }
+            transfer.info.state = TransferState::Cancelled;
+            (transfer.info.clone(), transfer.connection.clone())
+        };
+
+        connection.lock().unwrap().remove_channel(&info.id);
+        self.emit(&info);
+        Ok(())
+    }
+
+    /// Cancels everything queued or running on a session.
+    pub fn cancel_session(&self, session_id: &str) {
+        self.cancel_where(|t| t.info.session_id == session_id);
+    }
+
+    /// Cancels everything queued or running on a connection.
+    pub fn cancel_connection(&self, connection: &Arc<Mutex<SshConnection>>) {
+        self.cancel_where(|t| Arc::ptr_eq(&t.connection, connection));
+    }
+
+    fn cancel_where(&self, matches: impl Fn(&Transfer) -> bool) {
+        let ids: Vec<String> = self
+            .transfers
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|t| matches(t) && !t.info.state.is_finished())
+            .map(|t| t.info.id.clone())
+            .collect();
+        for id in ids {
+            self.cancel(&id).ok();
+        }
+    }
+
+    /// Queues a failed or cancelled transfer again, resuming from whatever it
+    /// already wrote. `session_id` and `connection` may point at a different
+    /// session than the original, e.g. after reconnecting.
+    pub fn retry(
+        self: &Arc<Self>,
+        transfer_id: &str,
+        session_id: &str,
+        connection: Arc<Mutex<SshConnection>>,
+    ) -> Result<TransferInfo, SshError> {
+        // Held before the transfer is queued, so it can't finish first
+        let added = connection.lock().unwrap().add_channel(transfer_id);
+        let info = match self.requeue(transfer_id, session_id, connection.clone()) {
+            Ok(info) => info,
+            Err(e) => {
+                if added {
+                    connection.lock().unwrap().remove_channel(transfer_id);
+                }
+                return Err(e);
+            }
+        };
+
+        self.emit(&info);
+        self.pump();
+        Ok(info)
+    }
+
+    fn requeue(
+        &self,
+        transfer_id: &str,
+        session_id: &str,
+        connection: Arc<Mutex<SshConnection>>,
+    ) -> Result<TransferInfo, SshError> {
+        let mut transfers = self.transfers.lock().unwrap();
+        let transfer = transfers
+            .iter_mut()
+            .find(|t| t.info.id == transfer_id)
+            .ok_or(SshError::TransferNotFound)?;
+        if !matches!(transfer.info.state, TransferState::Failed | TransferState::Cancelled) {
+            return Err(SshError::SftpFailed("Only failed or cancelled transfers can be retried".to_string()));
+        }
+
+        transfer.connection = connection;
+        transfer.resume = true;
+        transfer.cancel = Arc::new(AtomicBool::new(false));
+        transfer.info.session_id = session_id.to_string();
+        transfer.info.state = TransferState::Queued;
+        transfer.info.bytes_per_second = 0;
+        transfer.info.eta_seconds = None;
+        transfer.info.error = None;
+        Ok(transfer.info.clone())
+    }
+
+    pub fn clear_finished(&self) {
+        self.transfers.lock().unwrap().retain(|t| !t.info.state.is_finished());
+    }
+
+    fn emit(&self, info: &TransferInfo) {
+        if let Err(e) = self.app.emit("transfer-progress", info) {
+            warn!("Failed to emit progress for transfer {}: {}", info.id, e);
+        }
+    }
+
+    /// Starts queued transfers while their connection is below the limit.
+    fn pump(self: &Arc<Self>) {
+        let max_concurrent = self.max_concurrent.load(Ordering::Relaxed);
+        let mut started = Vec::new();
+        {
+            let mut transfers = self.transfers.lock().unwrap();
+            for index in 0..transfers.len() {
+                if transfers[index].info.state != TransferState::Queued {
+                    continue;
+                }
+                let connection = &transfers[index].connection;
+                let running = transfers
+                    .iter()
+                    .filter(|t| t.info.state == TransferState::Running && Arc::ptr_eq(&t.connection, connection))
+                    .count();
+                if running >= max_concurrent {
+                    continue;
+                }
+
+                let transfer = &mut transfers[index];
+                transfer.info.state = TransferState::Running;
+                started.push((
+                    transfer.info.clone(),
+                    transfer.connection.clone(),
+                    transfer.resume,
+                    transfer.cancel.clone(),
+                ));
+            }
+        }
+
+        for (info, connection, resume, cancel) in started {
+            self.emit(&info);
+            let queue = self.clone();
+            let result = std::thread::Builder::new()
+                .name(format!("sftp-transfer-{}", &info.id[..8]))
+                .spawn(move || queue.run(info, connection, resume, cancel));
+            if let Err(e) = result {
+                warn!("Failed to spawn transfer thread: {}", e);
+            }
+        }
+    }
+
+    fn run(
+        self: Arc<Self>,
+        info: TransferInfo,
+        connection: Arc<Mutex<SshConnection>>,
+        resume: bool,
+        cancel: Arc<AtomicBool>,
+    ) {
+        info!("Starting {:?} {} <-> {}", info.direction, info.local_path, info.remote_path);
+        let local_path = PathBuf::from(&info.local_path);
+        let remote_path = PathBuf::from(&info.remote_path);
+
+        let started = Instant::now();
+        let mut baseline = None;
+        let mut last_emit: Option<Instant> = None;
+        let mut progress = |done: u64, total: u64| {
+            if cancel.load(Ordering::Relaxed) {
+                return false;
+            }
+
+            // Bytes resumed from an earlier attempt don't count towards the rate
+            let baseline = *baseline.get_or_insert(done);
+            let elapsed = started.elapsed().as_secs_f64();
+            let rate = if elapsed > 0.0 { ((done - baseline) as f64 / elapsed) as u64 } else { 0 };
+            let eta = (rate > 0).then(|| total.saturating_sub(done) / rate);
+
+            if last_emit.map_or(true, |at| at.elapsed() >= PROGRESS_INTERVAL) {
+                last_emit = Some(Instant::now());
+                if let Some(info) = self.update(&info.id, |info| {
+                    info.bytes_transferred = done;
+                    info.total_bytes = Some(total);
+                    info.bytes_per_second = rate;
+                    info.eta_seconds = eta;
+                }) {
+                    self.emit(&info);
+                }
+            }
+            true
+        };
+
+        let result = match info.direction {
+            TransferDirection::Upload => sftp::upload(&connection, &local_path, &remote_path, resume, &mut progress),
+            TransferDirection::Download => sftp::download(&connection, &remote_path, &local_path, resume, &mut progress),
+        };
+
+        let finished = self.update(&info.id, |info| {
+            info.eta_seconds = None;
+            match &result {
+                Ok(size) => {
+                    info.state = TransferState::Completed;
+                    info.bytes_transferred = *size;
+                    info.total_bytes = Some(*size);
+                }
+                Err(SshError::TransferCancelled) => info.state = TransferState::Cancelled,
+                Err(e) => {
+                    info.state = TransferState::Failed;
+                    info.error = Some(e.to_string());
+                }
+            }
+        });
+
+        match &result {
+            Ok(_) => info!("Transfer {} completed", info.id),
+            Err(e) => warn!("Transfer {} ended: {}", info.id, e),
+        }
+        connection.lock().unwrap().remove_channel(&info.id);
+        if let Some(info) = finished {
+            self.emit(&info);
+        }
+        self.pump();
+    }
+
+    fn update(&self, transfer_id: &str, f: impl FnOnce(&mut TransferInfo)) -> Option<TransferInfo> {
+        let mut transfers = self.transfers.lock().unwrap();
+        let transfer = transfers.iter_mut().find(|t| t.info.id == transfer_id)?;
+        f(&mut transfer.info);
+        Some(transfer.info.clone())
+    }
+}
@@ -0,0 +1,154 @@
+use ssh2::{Channel, Session};
+use std::io::{self, ErrorKind, Read, Write};
+use std::net::{Shutdown, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+
+const RELAY_BUFFER_SIZE: usize = 32 * 1024;
+const IDLE_POLL_MIN: Duration = Duration::from_millis(1);
+const IDLE_POLL_MAX: Duration = Duration::from_millis(20);
+
+/// Creates a connected pair of loopback sockets.
+///
+/// libssh2 needs a real socket to run a session over, so tunnelled sessions
+/// get one end while a relay thread pumps the other end into an SSH channel.
+pub fn socket_pair() -> io::Result<(TcpStream, TcpStream)> {
+    let listener = TcpListener::bind(("127.0.0.1", 0))?;
+    let client = TcpStream::connect(listener.local_addr()?)?;
+    loop {
+        let (server, peer) = listener.accept()?;
+        // Anything else on the machine could race us to the listener
+        if peer == client.local_addr()? {
+            client.set_nodelay(true)?;
+            server.set_nodelay(true)?;
+            return Ok((client, server));
+        }
+    }
+}
+
+/// Stop flag and traffic counters shared by one or more relays.
+///
+/// "Sent" bytes went from the local stream into the channel, "received"
+/// bytes came out of the channel.
+#[derive(Default)]
+pub struct RelayControl {
+    pub stop: AtomicBool,
+    pub bytes_sent: AtomicU64,
+    pub bytes_received: AtomicU64,
+}
+
+impl RelayControl {
+    pub fn is_stopped(&self) -> bool {
+        self.stop.load(Ordering::Relaxed)
+    }
+}
+
+/// Copies bytes both ways between `channel` and `stream` until both sides
+/// have closed or `control` is stopped, then closes the channel.
+///
+/// Every libssh2 call is made in non-blocking mode while holding `lock`, so
+/// the session can be shared with other threads that follow the same rule.
+pub fn relay<T>(
+    session: &Session,
+    channel: &mut Channel,
+    mut stream: TcpStream,
+    lock: &Mutex<T>,
+    control: &RelayControl,
+) -> io::Result<()> {
+    stream.set_nonblocking(true)?;
+
+    let with_session = |f: &mut dyn FnMut() -> io::Result<usize>| -> io::Result<usize> {
+        let _guard = lock.lock().unwrap();
+        session.set_blocking(false);
+        let result = f();
+        session.set_blocking(true);
+        result
+    };
+
+    let mut buffer = vec![0u8; RELAY_BUFFER_SIZE];
+    let mut to_stream: Vec<u8> = Vec::new();
+    let mut to_channel: Vec<u8> = Vec::new();
+    let mut channel_eof = false;
+    let mut stream_eof = false;
+    let mut idle_poll = IDLE_POLL_MIN;
+
+    let result = loop {
+        if control.is_stopped() {
+            break Ok(());
+        }
+        let mut progressed = false;
+
+        // Channel -> stream
+        if to_stream.is_empty() && !channel_eof {
+            match with_session(&mut || channel.read(&mut buffer)) {
+                Ok(0) => {
+                    channel_eof = true;
+                    stream.shutdown(Shutdown::Write).ok();
+                    progressed = true;
+                }
+                Ok(n) => {
+                    to_stream.extend_from_slice(&buffer[..n]);
+                    progressed = true;
+                }
+                Err(e) if e.kind() == ErrorKind::WouldBlock => {}
+                Err(e) => break Err(e),
+            }
+        }
+        if !to_stream.is_empty() {
+            match stream.write(&to_stream) {
+                Ok(n) => {
+                    to_stream.drain(..n);
+                    control.bytes_received.fetch_add(n as u64, Ordering::Relaxed);
+                    progressed = true;
+                }
+                Err(e) if e.kind() == ErrorKind::WouldBlock => {}
+                Err(e) => break Err(e),
+            }
+        }
+
+        // Stream -> channel
+        if to_channel.is_empty() && !stream_eof {
+            match stream.read(&mut buffer) {
+                Ok(0) => {
+                    stream_eof = true;
+                    let _guard = lock.lock().unwrap();
+                    channel.send_eof().ok();
+                    progressed = true;
+                }
+                Ok(n) => {
+                    to_channel.extend_from_slice(&buffer[..n]);
+                    progressed = true;
+                }
+                Err(e) if e.kind() == ErrorKind::WouldBlock => {}
+                Err(e) => break Err(e),
+            }
+        }
+        if !to_channel.is_empty() {
+            match with_session(&mut || channel.write(&to_channel)) {
+                Ok(n) => {
+                    to_channel.drain(..n);
+                    control.bytes_sent.fetch_add(n as u64, Ordering::Relaxed);
+                    progressed = true;
+                }
+                Err(e) if e.kind() == ErrorKind::WouldBlock => {}
+                Err(e) => break Err(e),
+            }
+        }
+
+        if channel_eof && stream_eof && to_stream.is_empty() && to_channel.is_empty() {
+            break Ok(());
+        }
+
+        if progressed {
+            idle_poll = IDLE_POLL_MIN;
+        } else {
+            std::thread::sleep(idle_poll);
+            idle_poll = (idle_poll * 2).min(IDLE_POLL_MAX);
+        }
+    };
+
+    let _guard = lock.lock().unwrap();
+    channel.close().ok();
+    result
+}
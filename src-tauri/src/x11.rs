@@ -0,0 +1,333 @@
+use rand::Rng;
+use std::io::{self, ErrorKind, Read, Write};
+use std::net::{Shutdown, TcpStream};
+use std::sync::Arc;
+use log::{info, warn};
+
+use crate::inbound::Peer;
+
+/// The only X authorization protocol forwarded, as in OpenSSH.
+pub const AUTH_PROTOCOL: &str = "MIT-MAGIC-COOKIE-1";
+const COOKIE_LEN: usize = 16;
+/// First TCP port of X displays; display N listens on 6000 + N.
+const X11_TCP_PORT_BASE: u16 = 6000;
+/// Connection setup header: byte order, pad, protocol version, the lengths
+/// of the auth name and data, pad.
+const SETUP_HEADER_LEN: usize = 12;
+
+/// Where the local X server listens.
+#[derive(Debug, Clone)]
+enum Endpoint {
+    #[cfg(unix)]
+    Unix(std::path::PathBuf),
+    Tcp(String, u16),
+}
+
+/// The local display X11 channels are relayed to, and the cookies that
+/// stand in for its own on the server.
+///
+/// The server only ever sees a random cookie. Clients presenting it get the
+/// display's real cookie swapped in before their connection setup reaches
+/// the X server; anything else is turned away.
+#[derive(Debug)]
+pub struct X11Display {
+    name: String,
+    endpoint: Endpoint,
+    screen: u32,
+    fake_cookie: Vec<u8>,
+    /// From `xauth`; unset when the display doesn't need one.
+    real_cookie: Option<Vec<u8>>,
+}
+
+impl X11Display {
+    /// The display in `DISPLAY`. On Windows, where X servers rarely set it,
+    /// `localhost:0` is assumed.
+    pub fn from_env() -> Result<Self, String> {
+        let name = match std::env::var("DISPLAY") {
+            Ok(name) if !name.is_empty() => name,
+            _ if cfg!(windows) => "localhost:0".to_string(),
+            _ => return Err("DISPLAY is not set".to_string()),
+        };
+        let (endpoint, screen) = parse_display(&name)?;
+        let real_cookie = read_xauth(&name);
+        if real_cookie.is_none() {
+            info!("No xauth cookie for {}, forwarding without one", name);
+        }
+        let fake_cookie = (0..COOKIE_LEN).map(|_| rand::thread_rng().gen()).collect();
+
+        Ok(Self {
+            name,
+            endpoint,
+            screen,
+            fake_cookie,
+            real_cookie,
+        })
+    }
+
+    pub fn screen(&self) -> u32 {
+        self.screen
+    }
+
+    /// The cookie the server should hand to X clients, in hex as `x11-req`
+    /// carries it.
+    pub fn fake_cookie_hex(&self) -> String {
+        self.fake_cookie.iter().map(|byte| format!("{:02x}", byte)).collect()
+    }
+
+    /// Connects to the display, for one X11 channel.
+    pub fn open(self: &Arc<Self>) -> io::Result<X11Peer> {
+        let stream = match &self.endpoint {
+            #[cfg(unix)]
+            Endpoint::Unix(path) => {
+                let stream = std::os::unix::net::UnixStream::connect(path)?;
+                stream.set_nonblocking(true)?;
+                Stream::Unix(stream)
+            }
+            Endpoint::Tcp(host, port) => {
+                let stream = TcpStream::connect((host.as_str(), *port))?;
+                stream.set_nonblocking(true)?;
+                stream.set_nodelay(true).ok();
+                Stream::Tcp(stream)
+            }
+        };
+        Ok(X11Peer {
+            display: self.clone(),
+            stream,
+            setup: Some(Vec::new()),
+            pending: Vec::new(),
+            closing: false,
+            closed: false,
+        })
+    }
+
+    /// Checks a client's connection setup against the fake cookie and
+    /// returns it carrying the real one instead.
+    fn rewrite_setup(&self, setup: &[u8]) -> io::Result<Vec<u8>> {
+        let big_endian = setup[0] == b'B';
+        let (name, data) = setup_auth(setup);
+        if name != AUTH_PROTOCOL.as_bytes() || data != self.fake_cookie.as_slice() {
+            return Err(io::Error::new(
+                ErrorKind::PermissionDenied,
+                format!("X11 client offered the wrong cookie for {}", self.name),
+            ));
+        }
+
+        let (name, data): (&[u8], &[u8]) = match &self.real_cookie {
+            Some(cookie) => (AUTH_PROTOCOL.as_bytes(), cookie),
+            None => (&[], &[]),
+        };
+        let put_u16 = |value: usize| {
+            let value = value as u16;
+            if big_endian { value.to_be_bytes() } else { value.to_le_bytes() }
+        };
+        let mut rewritten = setup[..6].to_vec();
+        rewritten.extend_from_slice(&put_u16(name.len()));
+        rewritten.extend_from_slice(&put_u16(data.len()));
+        rewritten.extend_from_slice(&[0, 0]);
+        put_padded(&mut rewritten, name);
+        put_padded(&mut rewritten, data);
+        Ok(rewritten)
+    }
+}
+
+/// Parses `[host]:display[.screen]`. No host or `unix` means the local
+/// socket; a host that is a path (XQuartz's launchd socket) is the socket.
+fn parse_display(name: &str) -> Result<(Endpoint, u32), String> {
+    let invalid = || format!("Invalid DISPLAY: {}", name);
+    let (host, rest) = name.rsplit_once(':').ok_or_else(invalid)?;
+    let (number, screen) = match rest.split_once('.') {
+        Some((number, screen)) => (number, screen.parse().map_err(|_| invalid())?),
+        None => (rest, 0),
+    };
+    let number: u16 = number.parse().map_err(|_| invalid())?;
+
+    #[cfg(unix)]
+    {
+        if host.starts_with('/') {
+            let path = format!("{}:{}", host, number);
+            return Ok((Endpoint::Unix(path.into()), screen));
+        }
+        if host.is_empty() || host == "unix" {
+            let path = format!("/tmp/.X11-unix/X{}", number);
+            return Ok((Endpoint::Unix(path.into()), screen));
+        }
+    }
+    let host = if host.is_empty() { "localhost" } else { host };
+    let port = X11_TCP_PORT_BASE.checked_add(number).ok_or_else(invalid)?;
+    Ok((Endpoint::Tcp(host.to_string(), port), screen))
+}
+
+/// The display's `MIT-MAGIC-COOKIE-1`, as `xauth list` reports it.
+fn read_xauth(display: &str) -> Option<Vec<u8>> {
+    let output = std::process::Command::new("xauth")
+        .args(["list", display])
+        .output()
+        .map_err(|e| warn!("Failed to run xauth: {}", e))
+        .ok()?;
+    let listing = String::from_utf8_lossy(&output.stdout);
+    let cookie = listing.lines().find_map(|line| {
+        let mut fields = line.split_whitespace().skip(1);
+        match (fields.next(), fields.next()) {
+            (Some(AUTH_PROTOCOL), Some(cookie)) => Some(cookie),
+            _ => None,
+        }
+    })?;
+    decode_hex(cookie)
+}
+
+fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+    hex.as_bytes()
+        .chunks(2)
+        .map(|pair| {
+            let pair = std::str::from_utf8(pair).ok().filter(|pair| pair.len() == 2)?;
+            u8::from_str_radix(pair, 16).ok()
+        })
+        .collect()
+}
+
+fn padded_len(len: usize) -> usize {
+    (len + 3) & !3
+}
+
+fn put_padded(buf: &mut Vec<u8>, data: &[u8]) {
+    buf.extend_from_slice(data);
+    buf.resize(buf.len() + padded_len(data.len()) - data.len(), 0);
+}
+
+fn setup_u16(setup: &[u8], at: usize) -> usize {
+    let bytes = [setup[at], setup[at + 1]];
+    if setup[0] == b'B' {
+        u16::from_be_bytes(bytes) as usize
+    } else {
+        u16::from_le_bytes(bytes) as usize
+    }
+}
+
+/// Length of the connection setup at the start of `buffered`, once enough
+/// of it is there to tell.
+fn setup_len(buffered: &[u8]) -> io::Result<Option<usize>> {
+    match buffered.first() {
+        None => return Ok(None),
+        Some(b'B' | b'l') => {}
+        Some(_) => return Err(io::Error::new(ErrorKind::InvalidData, "Not an X11 connection setup")),
+    }
+    if buffered.len() < SETUP_HEADER_LEN {
+        return Ok(None);
+    }
+    let name_len = padded_len(setup_u16(buffered, 6));
+    let data_len = padded_len(setup_u16(buffered, 8));
+    Ok(Some(SETUP_HEADER_LEN + name_len + data_len))
+}
+
+/// The auth protocol name and data of a complete connection setup.
+fn setup_auth(setup: &[u8]) -> (&[u8], &[u8]) {
+    let name_len = setup_u16(setup, 6);
+    let data_len = setup_u16(setup, 8);
+    let name = &setup[SETUP_HEADER_LEN..SETUP_HEADER_LEN + name_len];
+    let data_start = SETUP_HEADER_LEN + padded_len(name_len);
+    (name, &setup[data_start..data_start + data_len])
+}
+
+enum Stream {
+    #[cfg(unix)]
+    Unix(std::os::unix::net::UnixStream),
+    Tcp(TcpStream),
+}
+
+impl Stream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            #[cfg(unix)]
+            Stream::Unix(stream) => stream.read(buf),
+            Stream::Tcp(stream) => stream.read(buf),
+        }
+    }
+
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        match self {
+            #[cfg(unix)]
+            Stream::Unix(stream) => stream.write(data),
+            Stream::Tcp(stream) => stream.write(data),
+        }
+    }
+
+    fn shutdown_write(&self) {
+        match self {
+            #[cfg(unix)]
+            Stream::Unix(stream) => stream.shutdown(Shutdown::Write).ok(),
+            Stream::Tcp(stream) => stream.shutdown(Shutdown::Write).ok(),
+        };
+    }
+}
+
+/// One X client on the server, connected to the local display. Its
+/// connection setup is held back until complete so the cookie can be
+/// checked and replaced.
+pub struct X11Peer {
+    display: Arc<X11Display>,
+    stream: Stream,
+    /// The setup received so far; `None` once it has been passed on.
+    setup: Option<Vec<u8>>,
+    /// Written to us but not yet taken by the X server.
+    pending: Vec<u8>,
+    closing: bool,
+    closed: bool,
+}
+
+impl X11Peer {
+    /// Passes as much of `pending` on as the X server takes, and shuts down
+    /// the write side once it's empty after `close_write`.
+    fn flush_pending(&mut self) -> io::Result<()> {
+        while !self.pending.is_empty() {
+            match self.stream.write(&self.pending) {
+                Ok(0) => return Err(ErrorKind::WriteZero.into()),
+                Ok(n) => {
+                    self.pending.drain(..n);
+                }
+                Err(e) if e.kind() == ErrorKind::WouldBlock => return Ok(()),
+                Err(e) => return Err(e),
+            }
+        }
+        if self.closing && !self.closed {
+            self.stream.shutdown_write();
+            self.closed = true;
+        }
+        Ok(())
+    }
+}
+
+impl Peer for X11Peer {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.flush_pending()?;
+        self.stream.read(buf)
+    }
+
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        if let Some(setup) = &mut self.setup {
+            setup.extend_from_slice(data);
+            let Some(len) = setup_len(setup)? else {
+                return Ok(data.len());
+            };
+            if setup.len() < len {
+                return Ok(data.len());
+            }
+            let rest = setup.split_off(len);
+            self.pending = self.display.rewrite_setup(setup)?;
+            self.pending.extend_from_slice(&rest);
+            self.setup = None;
+            self.flush_pending()?;
+            return Ok(data.len());
+        }
+
+        self.flush_pending()?;
+        if !self.pending.is_empty() {
+            return Err(ErrorKind::WouldBlock.into());
+        }
+        self.stream.write(data)
+    }
+
+    fn close_write(&mut self) {
+        self.closing = true;
+        self.flush_pending().ok();
+    }
+}
@@ -9,6 +9,14 @@ class SSHTerminalApp {
         this.tabCounter = 0;
         this.terminals = new Map();
         this.sessions = new Map();
+        // Session IDs whose input the backend mirrors to each other
+        this.broadcastGroup = new Set();
+        // Output that arrives before connect_ssh has returned its session ID
+        this.pendingOutput = new Map();
+        // Close events that arrive before then, applied once the output is flushed
+        this.pendingClose = new Map();
+        // ID of the connect in progress, so closing the panel can cancel it
+        this.pendingConnectId = null;
         
         this.init();
     }
@@ -25,6 +33,8 @@ class SSHTerminalApp {
                 document.getElementById('app-version').textContent = `v${this.version}`;
                 console.log('Tauri initialized, version:', this.version);
                 
+                await this.listenForBackendEvents();
+                
                 // Ensure default sessions exist on first launch
                 await this.ensureDefaultSessions();
             } else {
@@ -37,6 +47,85 @@ class SSHTerminalApp {
         }
     }
 
+    async listenForBackendEvents() {
+        const { listen } = window.__TAURI__.event;
+        
+        await listen('host-key-prompt', (event) => {
+            this.handleHostKeyPrompt(event.payload);
+        });
+        
+        await listen('keyboard-interactive-prompt', (event) => {
+            this.handleKeyboardInteractivePrompt(event.payload);
+        });
+        
+        await listen('key-passphrase-prompt', (event) => {
+            this.handlePassphrasePrompt(event.payload);
+        });
+        
+        await listen('ssh-output', (event) => {
+            this.handleSessionOutput(event.payload.session_id, event.payload.data);
+        });
+        
+        await listen('ssh-closed', (event) => {
+            this.handleSessionClosed(event.payload);
+        });
+        
+        await listen('ssh-connection-lost', (event) => {
+            this.handleConnectionLost(event.payload.session_id, event.payload.reason);
+        });
+        
+        await listen('ssh-reconnect', (event) => {
+            this.handleReconnect(event.payload);
+        });
+        
+        await listen('broadcast-changed', (event) => {
+            this.handleBroadcastChanged(event.payload.session_ids);
+        });
+    }
+
+    async respondToPrompt(id, response) {
+        try {
+            await window.__TAURI__.core.invoke('respond_prompt', { id, response });
+        } catch (error) {
+            console.error('Prompt response failed:', error);
+        }
+    }
+
+    handleHostKeyPrompt(prompt) {
+        const target = prompt.port === 22 ? prompt.host : `[${prompt.host}]:${prompt.port}`;
+        const accepted = confirm(
+            `The authenticity of host '${target}' can't be established.\n\n` +
+            `${prompt.key_type} key fingerprint is ${prompt.fingerprint}.\n\n` +
+            `Trust this host and continue connecting?`
+        );
+        this.respondToPrompt(prompt.id, accepted);
+    }
+
+    handleKeyboardInteractivePrompt(request) {
+        const header = [request.name, request.instruction].filter(Boolean).join('\n');
+        const answers = [];
+        
+        for (const p of request.prompts) {
+            const message = header ? `${request.host}\n${header}\n\n${p.text}` : `${request.host}\n\n${p.text}`;
+            const answer = prompt(message);
+            if (answer === null) {
+                // Cancelled: abort the whole authentication attempt
+                this.respondToPrompt(request.id, null);
+                return;
+            }
+            answers.push(answer);
+        }
+        
+        this.respondToPrompt(request.id, answers);
+    }
+
+    handlePassphrasePrompt(request) {
+        const message = request.retry
+            ? `Incorrect passphrase, try again.\n\nPassphrase for key ${request.key}:`
+            : `Connecting to ${request.host}\n\nPassphrase for key ${request.key}:`;
+        this.respondToPrompt(request.id, prompt(message));
+    }
+
     showDebug(msg) {
         // Show errors visibly on iOS where we can't see console
         const el = document.getElementById('connection-status');
@@ -91,6 +180,7 @@ class SSHTerminalApp {
 
         // Connection panel
         document.getElementById('btn-close-connection').addEventListener('click', () => {
+            this.cancelConnect();
             this.hidePanel('connection');
         });
 
@@ -101,6 +191,13 @@ class SSHTerminalApp {
 
         document.getElementById('conn-auth-type').addEventListener('change', (e) => {
             this.updateAuthFields(e.target.value);
+            if (e.target.value === 'stored-key') {
+                this.populateStoredKeys();
+            }
+        });
+
+        document.getElementById('conn-proxy-mode').addEventListener('change', (e) => {
+            this.updateProxyFields(e.target.value);
         });
 
         document.getElementById('btn-load-saved').addEventListener('click', () => {
@@ -214,8 +311,10 @@ class SSHTerminalApp {
         
         // Terminal input handling
         terminal.onData((data) => {
-            if (sessionId && this.tabs.get(tabId).connected) {
-                this.sendToSession(sessionId, data);
+            // Read from the tab each time; reconnecting rebinds it to a new session
+            const current = this.tabs.get(tabId);
+            if (current.sessionId && current.connected) {
+                this.sendToSession(current.sessionId, data);
             }
         });
         
@@ -286,12 +385,20 @@ class SSHTerminalApp {
         const username = document.getElementById('conn-username').value;
         const authType = document.getElementById('conn-auth-type').value;
         const name = document.getElementById('conn-name').value || `${username}@${host}`;
+        const jumpHosts = this.getJumpHosts();
+        const charset = document.getElementById('conn-charset').value || null;
+        const forwardAgent = document.getElementById('conn-forward-agent').value || null;
+        const forwardX11 = document.getElementById('conn-forward-x11').checked;
+        const algorithms = this.getAlgorithms();
+        const proxy = this.getProxy();
         
         let authValue = '';
         if (authType === 'password') {
             authValue = document.getElementById('conn-password').value;
         } else if (authType === 'key') {
             authValue = document.getElementById('conn-key-path').value;
+        } else if (authType === 'stored-key') {
+            authValue = document.getElementById('conn-stored-key').value;
         }
 
         if (!window.__TAURI__?.core) {
@@ -301,16 +408,28 @@ class SSHTerminalApp {
 
         console.log('Attempting connection to:', host, port, username, authType);
         
+        const pendingId = crypto.randomUUID();
+        this.pendingConnectId = pendingId;
+        
         try {
             this.updateConnectionStatus('Connecting...');
             
             console.log('Calling Tauri invoke connect_ssh...');
             const sessionId = await window.__TAURI__.core.invoke('connect_ssh', {
-                host,
-                port,
-                username,
-                authType,
-                authValue,
+                config: {
+                    host,
+                    port,
+                    username,
+                    auth_type: authType,
+                    auth_value: authValue,
+                    jump_hosts: jumpHosts,
+                    charset,
+                    forward_agent: forwardAgent,
+                    forward_x11: forwardX11,
+                    algorithms,
+                    proxy,
+                },
+                pendingId,
             });
             
             console.log('Connection successful, session ID:', sessionId);
@@ -333,15 +452,34 @@ class SSHTerminalApp {
             this.updateConnectionStatus(`Connected to ${host}:${port}`);
             this.hidePanel('connection');
             
-            // Start reading output
-            this.startReadingOutput(sessionId);
+            this.flushPendingOutput(sessionId);
             
         } catch (error) {
+            if (String(error).includes('Connection cancelled')) {
+                this.updateConnectionStatus('Not connected');
+                return;
+            }
             console.error('Connection failed with error:', error);
             console.error('Error type:', typeof error);
             console.error('Error string:', String(error));
             this.updateConnectionStatus('Connection failed');
             alert(`Connection failed: ${error}`);
+        } finally {
+            if (this.pendingConnectId === pendingId) {
+                this.pendingConnectId = null;
+            }
+        }
+    }
+
+    async cancelConnect() {
+        const pendingId = this.pendingConnectId;
+        if (!pendingId || !window.__TAURI__?.core) return;
+        
+        try {
+            await window.__TAURI__.core.invoke('cancel_connect', { pendingId });
+        } catch (error) {
+            // It finished in the meantime
+            console.log('Cancel connect:', error);
         }
     }
 
@@ -380,33 +518,155 @@ class SSHTerminalApp {
         }
     }
 
-    async startReadingOutput(sessionId) {
-        const readLoop = async () => {
-            if (!this.sessions.has(sessionId)) return;
+    // Opens a new tab with another shell on the same connection, so there's no second login
+    async duplicateTab(tabId) {
+        const tab = this.tabs.get(tabId);
+        if (!tab?.sessionId || !tab.connected || !window.__TAURI__?.core) return;
+        
+        try {
+            const sessionId = await window.__TAURI__.core.invoke('open_shell_channel', {
+                sessionId: tab.sessionId,
+            });
+            const info = this.sessions.get(tab.sessionId);
             
-            try {
-                const output = await window.__TAURI__.core.invoke('read_output', { sessionId });
-                
-                if (output) {
-                    // Find tab with this session
-                    for (const [tabId, tab] of this.tabs) {
-                        if (tab.sessionId === sessionId) {
-                            tab.terminal.write(output);
-                            break;
-                        }
-                    }
-                }
-                
-                // Continue reading
-                setTimeout(readLoop, 10);
-            } catch (error) {
-                console.error('Read failed:', error);
-                // Session might have ended
-                this.disconnect(sessionId);
+            const newTabId = this.createNewTab(sessionId);
+            const newTab = this.tabs.get(newTabId);
+            newTab.connected = true;
+            if (info) {
+                this.sessions.set(sessionId, { ...info });
+                newTab.element.querySelector('.tab-title').textContent = info.name;
             }
-        };
+            
+            this.flushPendingOutput(sessionId);
+        } catch (error) {
+            console.error('Duplicate tab failed:', error);
+            alert(String(error));
+        }
+    }
+
+    async toggleBroadcast(tabId) {
+        const tab = this.tabs.get(tabId);
+        if (!tab?.sessionId || !window.__TAURI__?.core) return;
         
-        readLoop();
+        const command = this.broadcastGroup.has(tab.sessionId) ? 'remove_from_broadcast' : 'add_to_broadcast';
+        try {
+            await window.__TAURI__.core.invoke(command, { sessionId: tab.sessionId });
+        } catch (error) {
+            console.error('Broadcast toggle failed:', error);
+        }
+    }
+
+    handleBroadcastChanged(sessionIds) {
+        this.broadcastGroup = new Set(sessionIds);
+        this.tabs.forEach(tab => {
+            tab.element.classList.toggle('broadcast', this.broadcastGroup.has(tab.sessionId));
+        });
+    }
+
+    findTabBySession(sessionId) {
+        for (const [tabId, tab] of this.tabs) {
+            if (tab.sessionId === sessionId) {
+                return tab;
+            }
+        }
+        return null;
+    }
+
+    handleSessionOutput(sessionId, data) {
+        const tab = this.findTabBySession(sessionId);
+        if (tab) {
+            tab.terminal.write(data);
+            return;
+        }
+        
+        if (!this.pendingOutput.has(sessionId)) {
+            this.pendingOutput.set(sessionId, []);
+        }
+        this.pendingOutput.get(sessionId).push(data);
+    }
+
+    flushPendingOutput(sessionId) {
+        const pending = this.pendingOutput.get(sessionId);
+        this.pendingOutput.delete(sessionId);
+        
+        const tab = this.findTabBySession(sessionId);
+        if (tab && pending) {
+            pending.forEach(data => tab.terminal.write(data));
+        }
+        
+        const closed = this.pendingClose.get(sessionId);
+        if (closed) {
+            this.pendingClose.delete(sessionId);
+            this.handleSessionClosed(closed);
+        }
+    }
+
+    handleConnectionLost(sessionId, reason) {
+        const tab = this.findTabBySession(sessionId);
+        if (tab) {
+            tab.terminal.write(`\r\n\x1b[31mConnection lost: ${reason}\x1b[0m\r\n`);
+        }
+        this.updateConnectionStatus('Connection lost');
+    }
+
+    handleReconnect({ session_id: sessionId, state, attempt, max_attempts: maxAttempts, new_session_id: newSessionId, error }) {
+        const tab = this.findTabBySession(sessionId);
+        if (!tab) return;
+        
+        if (state === 'reconnecting') {
+            tab.connected = false;
+            tab.terminal.write(`\r\n\x1b[33mConnection dropped (${error}). Reconnecting...\x1b[0m\r\n`);
+            this.updateConnectionStatus('Reconnecting...');
+        } else if (state === 'attempt') {
+            this.updateConnectionStatus(`Reconnecting (attempt ${attempt}/${maxAttempts})...`);
+        } else if (state === 'restored') {
+            const info = this.sessions.get(sessionId);
+            this.sessions.delete(sessionId);
+            if (info) {
+                this.sessions.set(newSessionId, info);
+            }
+            
+            tab.sessionId = newSessionId;
+            tab.connected = true;
+            tab.terminal.write('\x1b[32mReconnected.\x1b[0m\r\n');
+            this.updateConnectionStatus(info ? `Connected to ${info.host}:${info.port}` : 'Connected');
+            this.flushPendingOutput(newSessionId);
+        } else if (state === 'gave_up') {
+            tab.terminal.write(`\x1b[31mReconnect failed after ${attempt} attempts: ${error}\x1b[0m\r\n`);
+            this.updateConnectionStatus('Connection lost');
+        }
+    }
+
+    handleSessionClosed(payload) {
+        const { session_id: sessionId, error, exit_status: exitStatus, exit_signal: exitSignal, signal_message: signalMessage } = payload;
+        if (!this.sessions.has(sessionId)) {
+            // A shell that closed before connect_ssh returned its session ID
+            if (this.pendingConnectId) {
+                this.pendingClose.set(sessionId, payload);
+            } else {
+                this.pendingOutput.delete(sessionId);
+            }
+            return;
+        }
+        this.pendingOutput.delete(sessionId);
+        
+        let message;
+        if (error) {
+            console.error('Session output failed:', error);
+            message = `\x1b[31mConnection closed: ${error}\x1b[0m`;
+        } else if (exitSignal) {
+            const detail = signalMessage ? ` (${signalMessage})` : '';
+            message = `\x1b[31mShell killed by signal ${exitSignal}${detail}\x1b[0m`;
+        } else if (exitStatus != null) {
+            message = `\x1b[90mShell exited with status ${exitStatus}\x1b[0m`;
+        }
+        const tab = this.findTabBySession(sessionId);
+        if (tab && message) {
+            tab.terminal.write(`\r\n${message}\r\n`);
+        }
+        
+        // Session ended on the remote side; release it in the backend too
+        this.disconnect(sessionId);
     }
 
     async saveSession() {
@@ -415,12 +675,21 @@ class SSHTerminalApp {
         const port = parseInt(document.getElementById('conn-port').value) || 22;
         const username = document.getElementById('conn-username').value;
         const authType = document.getElementById('conn-auth-type').value;
+        const jumpHosts = this.getJumpHosts();
+        const group = document.getElementById('conn-group').value.trim() || null;
+        const charset = document.getElementById('conn-charset').value || null;
+        const forwardAgent = document.getElementById('conn-forward-agent').value || null;
+        const forwardX11 = document.getElementById('conn-forward-x11').checked;
+        const algorithms = this.getAlgorithms();
+        const proxy = this.getProxy();
         
         let authValue = '';
         if (authType === 'password') {
             authValue = document.getElementById('conn-password').value;
         } else if (authType === 'key') {
             authValue = document.getElementById('conn-key-path').value;
+        } else if (authType === 'stored-key') {
+            authValue = document.getElementById('conn-stored-key').value;
         }
 
         if (!window.__TAURI__?.core) {
@@ -435,11 +704,20 @@ class SSHTerminalApp {
         try {
             await window.__TAURI__.core.invoke('save_session', {
                 name,
-                host,
-                port,
-                username,
-                authType,
-                authValue,
+                config: {
+                    host,
+                    port,
+                    username,
+                    auth_type: authType,
+                    auth_value: authValue,
+                    jump_hosts: jumpHosts,
+                    group,
+                    charset,
+                    forward_agent: forwardAgent,
+                    forward_x11: forwardX11,
+                    algorithms,
+                    proxy,
+                },
             });
             
             alert('Session saved successfully');
@@ -505,16 +783,43 @@ class SSHTerminalApp {
         document.getElementById('conn-port').value = session.port;
         document.getElementById('conn-username').value = session.username;
         document.getElementById('conn-auth-type').value = session.auth_type;
+        document.getElementById('conn-jump-hosts').value = (session.jump_hosts || []).join(', ');
+        document.getElementById('conn-group').value = session.group || '';
+        document.getElementById('conn-charset').value = session.charset || '';
+        document.getElementById('conn-forward-agent').value = session.forward_agent || '';
+        document.getElementById('conn-forward-x11').checked = !!session.forward_x11;
+        const algorithms = session.algorithms || {};
+        for (const [field, id] of this.algorithmFields()) {
+            document.getElementById(id).value = (algorithms[field] || []).join(', ');
+        }
+        const proxy = session.proxy || { mode: 'global' };
+        document.getElementById('conn-proxy-mode').value = proxy.mode;
+        // The password stays in secure storage; leaving the field blank keeps it
+        const proxyPassword = document.getElementById('conn-proxy-password');
+        proxyPassword.value = '';
+        proxyPassword.dataset.key = (proxy.mode === 'custom' && proxy.password_key) || '';
+        proxyPassword.placeholder = proxyPassword.dataset.key ? '(saved)' : '(optional)';
+        if (proxy.mode === 'custom') {
+            document.getElementById('conn-proxy-kind').value = proxy.kind;
+            document.getElementById('conn-proxy-host').value = proxy.host;
+            document.getElementById('conn-proxy-port').value = proxy.port;
+            document.getElementById('conn-proxy-username').value = proxy.username || '';
+        }
+        this.updateProxyFields(proxy.mode);
         
         this.updateAuthFields(session.auth_type);
         
-        if (session.auth_type === 'key') {
-            // Load key path from secure storage
+        if (session.auth_type === 'key' || session.auth_type === 'stored-key') {
+            // Load key path or stored key name from secure storage
             try {
                 const authValue = await window.__TAURI__.core.invoke('get_session_credentials', {
                     name: session.name,
                 });
-                document.getElementById('conn-key-path').value = authValue;
+                if (session.auth_type === 'key') {
+                    document.getElementById('conn-key-path').value = authValue;
+                } else {
+                    await this.populateStoredKeys(authValue);
+                }
             } catch (error) {
                 console.error('Failed to load credentials:', error);
             }
@@ -545,7 +850,78 @@ class SSHTerminalApp {
     updateAuthFields(authType) {
         document.getElementById('auth-password-group').classList.toggle('hidden', authType !== 'password');
         document.getElementById('auth-key-group').classList.toggle('hidden', authType !== 'key');
+        document.getElementById('auth-stored-key-group').classList.toggle('hidden', authType !== 'stored-key');
         document.getElementById('auth-agent-group').classList.toggle('hidden', authType !== 'agent');
+        document.getElementById('auth-interactive-group').classList.toggle('hidden', authType !== 'keyboard-interactive');
+    }
+
+    updateProxyFields(mode) {
+        document.getElementById('proxy-custom-group').classList.toggle('hidden', mode !== 'custom');
+    }
+
+    getProxy() {
+        const mode = document.getElementById('conn-proxy-mode').value;
+        if (mode !== 'custom') {
+            return { mode };
+        }
+        return {
+            mode,
+            kind: document.getElementById('conn-proxy-kind').value,
+            host: document.getElementById('conn-proxy-host').value.trim(),
+            port: parseInt(document.getElementById('conn-proxy-port').value) || 8080,
+            username: document.getElementById('conn-proxy-username').value || null,
+            password: document.getElementById('conn-proxy-password').value || null,
+            password_key: document.getElementById('conn-proxy-password').dataset.key || null,
+        };
+    }
+
+    async populateStoredKeys(selected = null) {
+        const select = document.getElementById('conn-stored-key');
+        if (!window.__TAURI__?.core) return;
+        
+        try {
+            const keys = await window.__TAURI__.core.invoke('list_ssh_keys');
+            select.innerHTML = '';
+            keys.forEach(keyName => {
+                const option = document.createElement('option');
+                option.value = keyName;
+                option.textContent = keyName;
+                select.appendChild(option);
+            });
+            if (selected) {
+                select.value = selected;
+            }
+        } catch (error) {
+            console.error('Failed to load SSH keys:', error);
+        }
+    }
+
+    getJumpHosts() {
+        return document.getElementById('conn-jump-hosts').value
+            .split(',')
+            .map(name => name.trim())
+            .filter(Boolean);
+    }
+
+    algorithmFields() {
+        return [
+            ['kex', 'conn-algo-kex'],
+            ['host_key', 'conn-algo-host-key'],
+            ['cipher', 'conn-algo-cipher'],
+            ['mac', 'conn-algo-mac'],
+            ['compression', 'conn-algo-compression'],
+        ];
+    }
+
+    getAlgorithms() {
+        const algorithms = {};
+        for (const [field, id] of this.algorithmFields()) {
+            algorithms[field] = document.getElementById(id).value
+                .split(',')
+                .map(name => name.trim())
+                .filter(Boolean);
+        }
+        return algorithms;
     }
 
     async browseForKey() {
@@ -625,6 +1001,7 @@ class SSHTerminalApp {
         
         document.getElementById('setting-verify-hosts').checked = localStorage.getItem('verify-hosts') !== 'false';
         document.getElementById('setting-strict-hosts').checked = localStorage.getItem('strict-hosts') !== 'false';
+        document.getElementById('setting-passphrase-cache').checked = localStorage.getItem('passphrase-cache') === 'true';
         document.getElementById('setting-lock-timeout').value = localStorage.getItem('lock-timeout') || 300;
     }
 
@@ -637,12 +1014,32 @@ class SSHTerminalApp {
         localStorage.setItem('window-opacity', document.getElementById('setting-opacity').value);
         localStorage.setItem('verify-hosts', document.getElementById('setting-verify-hosts').checked);
         localStorage.setItem('strict-hosts', document.getElementById('setting-strict-hosts').checked);
+        localStorage.setItem('passphrase-cache', document.getElementById('setting-passphrase-cache').checked);
         localStorage.setItem('lock-timeout', document.getElementById('setting-lock-timeout').value);
         
+        this.saveSecuritySettings();
         this.applySettings();
         this.hidePanel('settings');
     }
 
+    async saveSecuritySettings() {
+        if (!window.__TAURI__?.core) return;
+        
+        try {
+            const config = await window.__TAURI__.core.invoke('get_app_config');
+            const security = {
+                ...config.security,
+                verify_host_keys: document.getElementById('setting-verify-hosts').checked,
+                strict_host_key_checking: document.getElementById('setting-strict-hosts').checked,
+                ssh_key_passphrase_cache: document.getElementById('setting-passphrase-cache').checked,
+                auto_lock_timeout: parseInt(document.getElementById('setting-lock-timeout').value) || 300,
+            };
+            await window.__TAURI__.core.invoke('update_security_config', { security });
+        } catch (error) {
+            console.error('Failed to save security settings:', error);
+        }
+    }
+
     applySettings() {
         const theme = localStorage.getItem('theme') || 'dark';
         document.body.setAttribute('data-theme', theme);
@@ -952,6 +1349,22 @@ The private key has been securely stored.
             this.showConnectionPanel();
         }
         
+        // Ctrl/Cmd + Shift + D: Open another shell on the current tab's connection
+        if ((e.ctrlKey || e.metaKey) && e.shiftKey && e.key.toLowerCase() === 'd') {
+            e.preventDefault();
+            if (this.activeTabId) {
+                this.duplicateTab(this.activeTabId);
+            }
+        }
+        
+        // Ctrl/Cmd + Shift + B: Add/remove the current tab from the broadcast group
+        if ((e.ctrlKey || e.metaKey) && e.shiftKey && e.key.toLowerCase() === 'b') {
+            e.preventDefault();
+            if (this.activeTabId) {
+                this.toggleBroadcast(this.activeTabId);
+            }
+        }
+        
         // Ctrl/Cmd + ,: Settings
         if ((e.ctrlKey || e.metaKey) && e.key === ',') {
             e.preventDefault();